@@ -0,0 +1,1450 @@
+//! The board-independent application flow of a single wake cycle
+
+use crate::budget::AwakeBudget;
+use crate::config::{AppConfig, ConfigError, SensorPin, SensorPolarity};
+use crate::debug::{StatusLed, StatusLedMode, StatusLedSession};
+use crate::device::DeviceId;
+use crate::history::History;
+use crate::i2c_sensor::{AirReadout, AirSensor, AirSensorError};
+use crate::lifecycle::{LastReading, Lifecycle, ReadingAverage, Sequence, SleepProbe, Starvation, WakeCounter};
+use crate::mqtt::{BirthInfo, MqttClient, MqttSession, MqttStack, Reconnect};
+use crate::mqtt_codec::{MqttBuffer, MqttError};
+use crate::ntp::{Timestamp, WallClock};
+use crate::onewire::{OneWireError, SoilThermometer};
+use crate::ota::{OtaClient, Version};
+use crate::panic::{LastPanic, PanicCounter};
+use crate::payload::{MqttFormat, Payload};
+use crate::readout::{SensorReadout, TemperatureUnit};
+use crate::rtc::{Ds3231, RtcError, align_sleep};
+use crate::sensor::{
+    FAULT_HIGH_MILLIVOLTS, FAULT_LOW_MILLIVOLTS, Sensor, SensorError, VREF_MILLIVOLTS, WARMUP_SAMPLES, sensor_channel,
+};
+use crate::wifi::{Cyw43, Cyw43Config, Cyw43Session};
+use crate::{APP_TIMEOUT, Irqs, debug_println, panic, stack};
+use core::fmt::Display;
+use core::ops::ControlFlow;
+use embassy_executor::Spawner;
+use embassy_net::{Ipv4Address, Stack};
+use embassy_rp::Peri;
+use embassy_rp::peripherals::{
+    ADC, ADC_TEMP_SENSOR, DMA_CH0, DMA_CH1, FLASH, I2C0, PIN_4, PIN_5, PIN_22, PIN_23, PIN_24, PIN_25, PIN_26, PIN_27,
+    PIN_28, PIN_29, PIO0,
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use static_cell::StaticCell;
+
+/// The board-specific glue of the application flow
+#[allow(async_fn_in_trait, reason = "the firmware is single-threaded, so the future needs no `Send` bound")]
+pub trait Board: Sized {
+    /// The firmware version and git commit hash
+    const VERSION: &'static str;
+
+    /// The hardware cause of the last reset
+    fn reset_cause(&self) -> impl Display;
+    /// Whether the last reset was caused by an unstable supply voltage, so the scratch registers are unreliable
+    fn is_brownout(&self) -> bool;
+    /// Whether the last reset was caused by a watchdog timeout
+    fn is_watchdog_timeout(&self) -> bool;
+    /// The unique device ID
+    fn device_id(&self) -> DeviceId;
+
+    /// Sets a new watchdog timeout
+    fn set_timeout(&self, timeout: Duration);
+    /// The remaining time until the watchdog deadline expires
+    fn remaining(&self) -> Duration;
+    /// Feeds the watchdog and performs a light-sleep for the given duration, then performs a graceful reset
+    async fn reset_after(self, timeout: Duration) -> !;
+
+    /// Performs the sleep sequence of [`Self::reset_after`] in isolation, e.g. to measure the sleep current on the bench
+    async fn sleep_only(self, duration: Duration) -> ! {
+        // Mark the cycle as graceful so the next boot does not divert to the after-panic handler
+        Lifecycle::store(Lifecycle::LIGHTSLEEP);
+        debug_println!("[info] entering bench-mode sleep");
+        self.reset_after(duration).await
+    }
+}
+
+/// The peripherals and pins used by the application flow
+///
+/// # Note
+/// The pin assignment is the same on all supported boards, as they share the Pico form factor and the CYW43 radio.
+pub struct Hardware {
+    /// The ADC peripheral for the sensor, battery and chip temperature
+    pub adc: Peri<'static, ADC>,
+    /// The chip temperature sensor
+    pub adc_temp_sensor: Peri<'static, ADC_TEMP_SENSOR>,
+    /// The DMA channel for the radio SPI
+    pub dma_ch0: Peri<'static, DMA_CH0>,
+    /// The DMA channel for the ADC samples
+    pub dma_ch1: Peri<'static, DMA_CH1>,
+    /// The PIO peripheral for the radio SPI
+    pub pio0: Peri<'static, PIO0>,
+    /// The I2C peripheral for the air sensor and the external RTC
+    pub i2c0: Peri<'static, I2C0>,
+    /// The flash peripheral for the config and the reading history
+    pub flash: Peri<'static, FLASH>,
+    /// The I2C data pin (SDA)
+    pub pin_4: Peri<'static, PIN_4>,
+    /// The I2C clock pin (SCL)
+    pub pin_5: Peri<'static, PIN_5>,
+    /// The one-wire data pin of the soil thermometer
+    pub pin_22: Peri<'static, PIN_22>,
+    /// The radio power-select pin
+    pub pin_23: Peri<'static, PIN_23>,
+    /// The radio SPI data pin
+    pub pin_24: Peri<'static, PIN_24>,
+    /// The radio SPI chip-select pin
+    pub pin_25: Peri<'static, PIN_25>,
+    /// The alternative sensor input pin
+    pub pin_26: Peri<'static, PIN_26>,
+    /// The sensor power-select pin
+    pub pin_27: Peri<'static, PIN_27>,
+    /// The default sensor input pin
+    pub pin_28: Peri<'static, PIN_28>,
+    /// The radio SPI clock pin, which also samples the battery voltage
+    pub pin_29: Peri<'static, PIN_29>,
+}
+
+/// The error signal duration
+const ERROR_SIGNAL_DURATION: Duration = Duration::from_secs(5);
+/// The TCP and MQTT packet buffer size
+const MQTT_BUF_SIZE: usize = 1024;
+/// The buffer size for plain value payloads
+const VALUE_BUF_SIZE: usize = 64;
+/// The buffer size for structured payloads
+const PAYLOAD_BUF_SIZE: usize = 256;
+/// The minimum remaining watchdog budget to retry a failed MQTT cycle
+const CYCLE_BUDGET: Duration = Duration::from_secs(15);
+/// The minimum sleep duration after the MQTT server rejected the config, so we don't hammer it
+const CONFIG_REJECTED_SLEEP: Duration = Duration::from_secs(60 * 60);
+
+/// Runs a single wake cycle, and sleeps until the next one
+///
+/// # Note
+/// The cycle runs in phases: The sensors are read and the pre-radio gates may send the device back to sleep right away,
+/// then the radio is booted and the network is joined, and finally the MQTT cycle publishes the report before the
+/// shutdown sequence persists the pending updates.
+pub async fn run<B>(board: B, mut hw: Hardware, spawner: Spawner) -> !
+where
+    B: Board,
+{
+    /// The network socket count (at least 3 for DHCP, NTP and MQTT)
+    const NETWORK_SOCKETS: usize = 5;
+
+    /// Static CYW43 peripheral handle
+    static CYW43: StaticCell<Cyw43<NETWORK_SOCKETS>> = StaticCell::new();
+    /// Static radio control session
+    static RADIO: StaticCell<Cyw43Session> = StaticCell::new();
+    /// Static status LED control session
+    static LED: StaticCell<StatusLed> = StaticCell::new();
+
+    // Take reset info before doing anything else
+    let lifecycle_before_reset = Lifecycle::load();
+    debug_println!("[info] firmware version: {}", B::VERSION);
+    debug_println!("[info] lifecycle before reset: {:?}", lifecycle_before_reset);
+    let last_panic = LastPanic::load();
+    debug_println!("[info] last panic: {:?}", last_panic.as_deref().and_then(|location| str::from_utf8(location).ok()));
+    debug_println!("[info] reset cause: {}", board.reset_cause());
+    let starved = Starvation::load();
+    debug_println!("[info] expired watchdog deadlines: {}", starved);
+
+    // Derive the reset reason for the health summary
+    // Note: The after-panic handler resets into a regular cycle, so a pending panic location marks a crash. The hardware
+    //  cause is only consulted afterwards, as it may be stale after the software reset of the panic handler
+    let reset_reason = match (lifecycle_before_reset, &last_panic) {
+        (Some(_), Some(_)) => "panic",
+        _ if board.is_brownout() => "brownout",
+        (None, _) => "poweron",
+        (Some(_), None) if board.is_watchdog_timeout() => "watchdog",
+        (Some(_), None) => "wake",
+    };
+
+    // Mark the started watchdog
+    Lifecycle::store(Lifecycle::WATCHDOG);
+    debug_println!("[info] watchdog initialized");
+
+    // Load device config, and derive the sensor settings once
+    let config = AppConfig::try_load();
+    debug_println!("[info] loaded config: {:?}", config);
+    let settings = SensorSettings::new(config.as_ref());
+
+    // Read device ID
+    let device_id = board.device_id();
+    debug_println!("[info] device id: {}", device_id);
+
+    // Divert to sleep immediately in bench mode, so the sleep current can be measured without the radio
+    if let Ok(config) = &config
+        && config.BENCH_SLEEP_SECS > Duration::MIN
+    {
+        board.sleep_only(config.BENCH_SLEEP_SECS).await
+    }
+
+    // Divert to a read-print loop in dry-run mode, so the sensor wiring can be validated without WiFi/MQTT
+    if let Ok(config) = &config
+        && config.DRY_RUN
+    {
+        dry_run(&board, hw, config, settings).await
+    }
+
+    //
+    // Read the sensors and pass the pre-radio gates
+    //
+
+    // Read sensor, chip temperature and battery voltage
+    // Note: The battery is sampled via the radio clock pin, so this must happen before the radio is booted. Also, the ADC
+    //  draws some current, so ensure it is dropped asap
+    let channel = sensor_channel(settings.pin, hw.pin_26.reborrow(), hw.pin_28.reborrow());
+    let temperature = settings.temperature.then_some(hw.adc_temp_sensor.reborrow());
+    let (adc, dma, powerselect, battery) =
+        (hw.adc.reborrow(), hw.dma_ch1.reborrow(), hw.pin_27.reborrow(), hw.pin_29.reborrow());
+    let mut sensor = Sensor::new(adc, Irqs, dma, powerselect, settings.polarity, channel, temperature, battery);
+    let mut readings = settings.read(&mut sensor).await;
+    drop(sensor);
+    debug_println!("[info] read sensor values");
+
+    // Go back to sleep without booting the radio if a gate applies
+    let wake =
+        match pre_radio_gates(&mut hw, &config, lifecycle_before_reset, last_panic.is_none(), &mut readings).await {
+            ControlFlow::Continue(wake) => wake,
+            ControlFlow::Break(sleep) => board.reset_after(sleep).await,
+        };
+    let Wake { schedule, rtc_present, sleep_drain, heartbeat, millivolts } = wake;
+
+    //
+    // Boot the radio
+    //
+
+    // Setup radio and init network stack
+    let radio =
+        CYW43.init(Cyw43Config::new(hw.pio0, Irqs, hw.dma_ch0).set_pins(hw.pin_23, hw.pin_25, hw.pin_24, hw.pin_29));
+    let (radio, network) = radio.boot(&spawner).await;
+    Lifecycle::store(Lifecycle::RADIOINIT);
+    debug_println!("[info] initialized radio");
+
+    // Setup radio and LED control sessions
+    let radio = RADIO.init(radio);
+    let led_interval = config.as_ref().map_or(StatusLed::DEFAULT_INTERVAL, |config| config.LED_BLINK_MS);
+    let led = LED.init(StatusLed::new(radio, led_interval));
+    let led = led.start(&spawner);
+
+    // We now have everything set up to divert to the after-panic handler if appropriate
+    // Note: The scratch registers are cleared on power-up, so a missing lifecycle is a clean first boot and not a crash.
+    //  A brown-out may leave the scratch registers intact, but is not a crash either
+    let graceful =
+        matches!(lifecycle_before_reset, None | Some(Lifecycle::LIGHTSLEEP | Lifecycle::CUTOFF)) || board.is_brownout();
+    if !graceful {
+        after_panic(board, radio, network, &led, &config, device_id, last_panic.as_deref()).await
+    }
+
+    // Divert to sleep if the config is invalid, so we don't try to connect with bogus values
+    let mut config = match config {
+        Ok(config) => config,
+        Err(error) => {
+            // Signal the invalid config to the user
+            debug_println!("[warn] {}", error);
+            led.set(StatusLedMode::BlinkSlow);
+            Timer::after(ERROR_SIGNAL_DURATION).await;
+
+            // Sleep with the lenient config as the sleep duration may still be valid
+            let config = AppConfig::load();
+            let sleep = align_sleep(schedule, config.SENSOR_SLEEP_SECS);
+            sleep_after_error(board, radio, hw.flash.reborrow(), None, &config, sleep, "config error").await
+        }
+    };
+
+    //
+    // Enter main application logic
+    //
+    Lifecycle::store(Lifecycle::APPINIT);
+    AwakeBudget::start(config.AWAKE_BUDGET_SECS, &spawner);
+
+    // Blink the last digit of the device ID after power-up, so a freshly deployed device can be matched to its topic
+    // Note: The digit `0` is shown as 16 blinks, and wake-ups skip this to not waste battery every cycle
+    if reset_reason == "poweron" {
+        let flashes = match device_id.last_digit() {
+            0 => 16,
+            digit => digit,
+        };
+        led.identify(flashes).await;
+    }
+    led.set(StatusLedMode::On);
+
+    // Read the optional external air sensor (SCL on GP5, SDA on GP4), and convert its temperature to the config unit
+    let air_readings = AirSensor::new(hw.i2c0.reborrow(), Irqs, hw.pin_5.reborrow(), hw.pin_4.reborrow()).read().await;
+    let air_readings =
+        air_readings.map(|air| AirReadout { temperature: config.SENSOR_TEMP_UNIT.convert(air.temperature), ..air });
+    match &air_readings {
+        Ok(_) => debug_println!("[info] read air sensor values"),
+        Err(AirSensorError::Missing) => debug_println!("[info] no air sensor connected"),
+        Err(error) => debug_println!("[warn] {}", error),
+    }
+
+    // Read the optional external soil thermometer (data on GP22), and convert its temperature to the config unit
+    let soil_temperature = SoilThermometer::new(hw.pin_22).read().await;
+    let soil_temperature = soil_temperature.map(|celsius| config.SENSOR_TEMP_UNIT.convert(celsius));
+    match &soil_temperature {
+        Ok(_) => debug_println!("[info] read soil temperature"),
+        Err(OneWireError::Missing) => debug_println!("[info] no soil temperature sensor connected"),
+        Err(error) => debug_println!("[warn] {}", error),
+    }
+
+    // Compare the sensor voltage against the optional alert thresholds
+    // Note: There is no calibration to a moisture percentage, so the thresholds are raw sensor voltages in millivolts
+    let thresholds = config.SENSOR_LOW > 0 || config.SENSOR_HIGH > 0;
+    let alert = millivolts.filter(|_| thresholds).map(|millivolts| match millivolts {
+        millivolts if config.SENSOR_LOW > 0 && millivolts < config.SENSOR_LOW => Some("low"),
+        millivolts if config.SENSOR_HIGH > 0 && millivolts > config.SENSOR_HIGH => Some("high"),
+        _ => None,
+    });
+
+    //
+    // Join the network
+    //
+    let (rssi, ip) = match connect_network(&board, radio, network, &config, &led).await {
+        Ok(connectivity) => connectivity,
+        Err(abort) => abort.sleep(board, radio, hw.flash.reborrow(), millivolts, &config, schedule).await,
+    };
+
+    // Synchronize wall clock if configured
+    let clock = WallClock::sync(network, config.NTP_SERVER).await;
+    debug_println!("[info] synchronized wall clock: {:?}", clock.map(|clock| clock.now()));
+    if let Some(clock) = &clock
+        && rtc_present
+    {
+        // Keep the external RTC in sync, which also restores its time after the backup battery has been replaced
+        match Ds3231::new(hw.i2c0.reborrow(), Irqs, hw.pin_5.reborrow(), hw.pin_4.reborrow()).set(clock).await {
+            Ok(()) => debug_println!("[info] updated external rtc"),
+            Err(error) => debug_println!("[warn] {}", error),
+        }
+    }
+
+    // Take reading timestamp, and fall back to the external RTC if there is no synchronized clock
+    let timestamp = clock.or(schedule).map(|clock| clock.now());
+
+    // Prefer the synchronized clock for the wake schedule if an external RTC is present
+    let schedule = clock.filter(|_| rtc_present).or(schedule);
+
+    //
+    // Publish the report
+    //
+
+    // Parse the firmware version for the update check once, and skip the check if the version is not a plain version
+    let firmware_version = Version::parse(B::VERSION);
+    if firmware_version.is_none() {
+        debug_println!("[warn] skipping firmware update checks for unparsable version: {}", B::VERSION);
+    }
+
+    // Number the reading, so the consumer can detect lost or duplicated readings
+    // Note: Retries within the same wake cycle republish the same sequence number
+    let report = Report {
+        readings,
+        air_readings,
+        soil_temperature,
+        millivolts,
+        alert,
+        heartbeat,
+        sleep_drain,
+        sequence: Sequence::next(),
+        timestamp,
+        rssi,
+        ip,
+        device_id,
+        version: B::VERSION,
+        firmware_version,
+        reset_reason,
+        starved,
+    };
+
+    // Run the MQTT cycle
+    let mut mqtt_stack = MqttStack::<MQTT_BUF_SIZE>::new(network);
+    let cycle = mqtt_cycle(&board, &mut mqtt_stack, &mut config, &led, &mut hw.flash, &report, last_panic).await;
+    let config_changed = match cycle {
+        Ok(config_changed) => config_changed,
+        Err(abort) => abort.sleep(board, radio, hw.flash.reborrow(), millivolts, &config, schedule).await,
+    };
+
+    //
+    // Shut down and sleep
+    //
+    shutdown(board, radio, hw.flash, &config, config_changed, schedule).await
+}
+
+/// The sensor settings, derived once from the config, or the defaults if the config is invalid
+#[derive(Debug, Clone, Copy)]
+struct SensorSettings {
+    /// The active level of the sensor power-select pin
+    polarity: SensorPolarity,
+    /// The sensor input pin
+    pin: SensorPin,
+    /// Whether to sample the chip temperature
+    temperature: bool,
+    /// The duration of a sampling run, or zero for the default
+    sample_duration: Duration,
+    /// The amount of leading samples to discard
+    warmup: u16,
+    /// The ADC reference voltage in millivolts
+    vref_mv: u16,
+    /// The sensor voltage in millivolts below which a reading is implausible, or zero to disable the bound
+    fault_low: u16,
+    /// The sensor voltage in millivolts above which a reading is implausible, or zero to disable the bound
+    fault_high: u16,
+    /// Whether to compute the sample statistics
+    stats: bool,
+    /// The unit for temperatures
+    unit: TemperatureUnit,
+}
+impl SensorSettings {
+    /// The settings if the config is invalid
+    const DEFAULT: Self = Self {
+        polarity: SensorPolarity::ActiveHigh,
+        pin: SensorPin::Gpio28,
+        temperature: true,
+        sample_duration: Duration::MIN,
+        warmup: WARMUP_SAMPLES,
+        vref_mv: VREF_MILLIVOLTS,
+        fault_low: FAULT_LOW_MILLIVOLTS,
+        fault_high: FAULT_HIGH_MILLIVOLTS,
+        stats: false,
+        unit: TemperatureUnit::Celsius,
+    };
+
+    /// Derives the settings from the given config, or falls back to the defaults if the config is invalid
+    fn new(config: Result<&AppConfig, &ConfigError>) -> Self {
+        let Ok(config) = config else {
+            // Use the defaults
+            return Self::DEFAULT;
+        };
+        Self {
+            polarity: config.SENSOR_POLARITY,
+            pin: config.SENSOR_PIN,
+            temperature: config.SENSOR_TEMPERATURE,
+            sample_duration: config.SENSOR_SAMPLE_MS,
+            warmup: config.SENSOR_WARMUP,
+            vref_mv: config.SENSOR_VREF_MV,
+            fault_low: config.SENSOR_FAULT_LOW,
+            fault_high: config.SENSOR_FAULT_HIGH,
+            stats: config.SENSOR_STATS,
+            unit: config.SENSOR_TEMP_UNIT,
+        }
+    }
+
+    /// Applies the sample duration to the given sensor, and reads it with these settings
+    async fn read(&self, sensor: &mut Sensor<'_, DMA_CH1>) -> Result<SensorReadout, SensorError> {
+        sensor.set_sample_duration(self.sample_duration);
+        debug_println!("[info] sampling duration: {}ms", sensor.sample_duration().as_millis());
+        sensor.read(self.warmup, self.vref_mv, self.fault_low, self.fault_high, self.stats, self.unit).await
+    }
+}
+
+/// The state of a wake cycle that has passed the pre-radio gates
+struct Wake {
+    /// The external RTC time for the wake schedule, if any
+    schedule: Option<WallClock>,
+    /// Whether an external RTC is connected
+    rtc_present: bool,
+    /// The battery drain in millivolts during the deep-sleep self-test, if it has been evaluated with this cycle
+    sleep_drain: Option<i32>,
+    /// The wake count if a heartbeat is due
+    heartbeat: Option<u32>,
+    /// The quantized sensor voltage in millivolts, if the sensor has been read
+    millivolts: Option<u16>,
+}
+
+/// The readings and device information of a wake cycle, which are published by the MQTT cycle
+struct Report {
+    /// The sensor, chip temperature and battery readings
+    readings: Result<SensorReadout, SensorError>,
+    /// The optional air sensor readings
+    air_readings: Result<AirReadout, AirSensorError>,
+    /// The optional soil temperature
+    soil_temperature: Result<f64, OneWireError>,
+    /// The quantized sensor voltage in millivolts, if the sensor has been read
+    millivolts: Option<u16>,
+    /// The alert state if thresholds are configured, with the reason if the sensor voltage is out of range
+    alert: Option<Option<&'static str>>,
+    /// The wake count if a heartbeat is due
+    heartbeat: Option<u32>,
+    /// The battery drain in millivolts during the deep-sleep self-test, if any
+    sleep_drain: Option<i32>,
+    /// The reading sequence number
+    sequence: u16,
+    /// The reading timestamp, if a wall clock is available
+    timestamp: Option<Timestamp>,
+    /// The wifi signal strength from the join scan
+    rssi: i32,
+    /// The assigned IP address, if any
+    ip: Option<Ipv4Address>,
+    /// The unique device ID
+    device_id: DeviceId,
+    /// The firmware version and git commit hash
+    version: &'static str,
+    /// The parsed firmware version for the update check, if it is a plain version
+    firmware_version: Option<Version>,
+    /// The reason of the last reset
+    reset_reason: &'static str,
+    /// The amount of expired watchdog deadlines
+    starved: u8,
+}
+impl Report {
+    /// Encodes the entire readout as structured payload, or returns `None` if the sensor has not been read
+    ///
+    /// # Note
+    /// The readout bundles all readings, so it can be mirrored to the secondary MQTT server as well.
+    fn readout(&self, format: MqttFormat) -> Option<Result<MqttBuffer<PAYLOAD_BUF_SIZE>, MqttError>> {
+        let readings = self.readings.as_ref().ok()?;
+        let mut payload = Payload::<PAYLOAD_BUF_SIZE>::new(format);
+        payload.float("voltage", readings.sensor).float("battery", readings.battery);
+        if let Some(temperature) = readings.temperature {
+            payload.float("temperature", temperature);
+        }
+        payload.unsigned("seq", self.sequence as u64);
+        if let Ok(air_readings) = &self.air_readings {
+            payload.float("air_temp", air_readings.temperature).float("humidity", air_readings.humidity);
+        }
+        if let Ok(soil_temperature) = self.soil_temperature {
+            payload.float("soil_temp", soil_temperature);
+        }
+        Some(payload.finish())
+    }
+}
+
+/// The state of the MQTT cycle that is kept across retries
+struct CycleState {
+    /// Whether the birth message has been published
+    born: bool,
+    /// Whether buffered readings from previous cycles are pending
+    backlog: bool,
+    /// Whether maintenance mode is requested
+    maintenance: bool,
+    /// Whether the config has been updated at runtime
+    config_changed: bool,
+    /// The pending panic location, until it has been published
+    last_panic: Option<LastPanic>,
+}
+
+/// An early end of the wake cycle after an error, which sends the device back to sleep
+#[derive(Debug, Clone, Copy)]
+enum Abort {
+    /// Retry with the next regular cycle, and buffer the reading so it can be published once connectivity returns
+    Retry(&'static str),
+    /// Sleep longer without buffering the reading, as retrying is pointless until the config is fixed
+    Rejected(&'static str),
+}
+impl Abort {
+    /// Sleeps according to the abort kind; see [`sleep_after_error`]
+    async fn sleep<B>(
+        self,
+        board: B,
+        radio: &Cyw43Session,
+        flash: Peri<'_, FLASH>,
+        millivolts: Option<u16>,
+        config: &AppConfig,
+        schedule: Option<WallClock>,
+    ) -> !
+    where
+        B: Board,
+    {
+        match self {
+            Self::Retry(reason) => {
+                let sleep = align_sleep(schedule, config.SENSOR_SLEEP_SECS);
+                sleep_after_error(board, radio, flash, millivolts, config, sleep, reason).await
+            }
+            Self::Rejected(reason) => {
+                let sleep = config.SENSOR_SLEEP_SECS.max(CONFIG_REJECTED_SLEEP);
+                sleep_after_error(board, radio, flash, None, config, sleep, reason).await
+            }
+        }
+    }
+}
+
+/// Reads and prints the sensors in a loop without WiFi/MQTT, so the sensor wiring can be validated
+///
+/// # Note
+/// The sensors are set up only once, as the sample buffer is a singleton.
+async fn dry_run<B>(board: &B, hw: Hardware, config: &AppConfig, settings: SensorSettings) -> !
+where
+    B: Board,
+{
+    // Setup the sensors
+    debug_println!("[info] entering dry-run mode");
+    let temperature = settings.temperature.then_some(hw.adc_temp_sensor);
+    let channel = sensor_channel(settings.pin, hw.pin_26, hw.pin_28);
+    let mut sensor =
+        Sensor::new(hw.adc, Irqs, hw.dma_ch1, hw.pin_27, settings.polarity, channel, temperature, hw.pin_29);
+    let mut air_sensor = AirSensor::new(hw.i2c0, Irqs, hw.pin_5, hw.pin_4);
+    let mut soil_thermometer = SoilThermometer::new(hw.pin_22);
+    loop {
+        // Keep the watchdog fed across the reading and the following sleep
+        board.set_timeout(APP_TIMEOUT + config.SENSOR_SLEEP_SECS);
+
+        // Read and print the sensor, chip temperature and battery voltage
+        match settings.read(&mut sensor).await {
+            Ok(readings) => {
+                debug_println!("[info] sensor voltage: {}", readings.sensor);
+                if readings.suspect {
+                    debug_println!("[warn] sensor voltage is implausible; check the probe wiring");
+                }
+                debug_println!("[info] raw sensor counts: {}", readings.sensor_raw);
+                if let Some(stats) = readings.stats {
+                    debug_println!("[info] sensor min/max/stddev: {}/{}/{}", stats.min, stats.max, stats.stddev);
+                }
+                if let Some(temperature) = readings.temperature {
+                    debug_println!("[info] system temperature: {}", temperature);
+                }
+                debug_println!("[info] battery voltage: {}", readings.battery);
+            }
+            Err(error) => debug_println!("[warn] {}", error),
+        }
+
+        // Read and print the optional external sensors
+        match air_sensor.read().await {
+            Ok(air_readings) => {
+                debug_println!("[info] air temperature: {}", settings.unit.convert(air_readings.temperature));
+                debug_println!("[info] air humidity: {}", air_readings.humidity);
+            }
+            Err(AirSensorError::Missing) => debug_println!("[info] no air sensor connected"),
+            Err(error) => debug_println!("[warn] {}", error),
+        }
+        match soil_thermometer.read().await {
+            Ok(soil_temperature) => {
+                debug_println!("[info] soil temperature: {}", settings.unit.convert(soil_temperature))
+            }
+            Err(OneWireError::Missing) => debug_println!("[info] no soil temperature sensor connected"),
+            Err(error) => debug_println!("[warn] {}", error),
+        }
+
+        // Wait for the next reading
+        Timer::after(config.SENSOR_SLEEP_SECS).await;
+    }
+}
+
+/// Evaluates the gates that send the device back to sleep without booting the radio, and returns the sleep duration if
+/// a gate applies
+///
+/// # Note
+/// The gates are the battery cutoff, the deep-sleep self-test, the accumulation of averaged readings, and unchanged
+/// readings. Except for the cutoff, they only follow a regular cycle, and they are skipped while a panic is pending, so
+/// a crash is still announced.
+async fn pre_radio_gates(
+    hw: &mut Hardware,
+    config: &Result<AppConfig, ConfigError>,
+    lifecycle_before_reset: Option<u32>,
+    no_panic: bool,
+    readings: &mut Result<SensorReadout, SensorError>,
+) -> ControlFlow<Duration, Wake> {
+    /// The minimum sleep duration while the battery is below the cutoff voltage
+    const BATTERY_CUTOFF_SLEEP: Duration = Duration::from_secs(24 * 60 * 60);
+    /// The voltage in millivolts by which the battery must recover above the cutoff to resume regular cycles
+    const BATTERY_HYSTERESIS_MV: u16 = 100;
+    /// The heartbeat interval in wakes if unchanged readings are skipped, but no heartbeat interval is configured
+    const HEARTBEAT_FALLBACK_EVERY: u32 = 10;
+
+    // Divert to long sleep without booting the radio if the battery is below the cutoff, so it is not deeply discharged
+    // Note: Once in cutoff, the battery must recover above the cutoff plus hysteresis, so the device does not chatter
+    //  around the threshold. The status LED is driven by the radio, so the cutoff is signaled via the debug log only
+    if let (Ok(config), Ok(readings)) = (config, &readings)
+        && config.BATTERY_CUTOFF_MV > 0
+    {
+        // Note: Float to int casts saturate, so out-of-range voltages are clamped
+        let millivolts = (readings.battery * 1000.0 + 0.5) as u16;
+        let in_cutoff = lifecycle_before_reset == Some(Lifecycle::CUTOFF);
+        let threshold = match in_cutoff {
+            true => config.BATTERY_CUTOFF_MV.saturating_add(BATTERY_HYSTERESIS_MV),
+            false => config.BATTERY_CUTOFF_MV,
+        };
+        if millivolts < threshold {
+            Lifecycle::store(Lifecycle::CUTOFF);
+            debug_println!("[warn] entering long sleep as the battery is below the cutoff: {}mV", millivolts);
+            return ControlFlow::Break(config.SENSOR_SLEEP_SECS.max(BATTERY_CUTOFF_SLEEP));
+        }
+        if in_cutoff {
+            debug_println!("[info] battery has recovered from the cutoff: {}mV", millivolts);
+        }
+    }
+
+    // Read the optional external RTC (on the air sensor bus), so the wake schedule can be aligned to the wall clock
+    // Note: Without a valid RTC time, the device falls back to sleeping for the plain interval
+    let rtc = Ds3231::new(hw.i2c0.reborrow(), Irqs, hw.pin_5.reborrow(), hw.pin_4.reborrow()).read().await;
+    let rtc_present = !matches!(rtc, Err(RtcError::Missing));
+    match &rtc {
+        Ok(clock) => debug_println!("[info] external rtc time: {}", clock.now()),
+        Err(RtcError::Missing) => debug_println!("[info] no external rtc connected"),
+        Err(error) => debug_println!("[warn] {}", error),
+    }
+    let schedule = rtc.ok();
+
+    // Evaluate a pending deep-sleep self-test, so the battery voltage delta can be published with this cycle
+    let battery = readings.as_ref().ok().map(|readings| readings.battery);
+    let sleep_drain = SleepProbe::take().zip(battery).map(|(before, after)| before - (after * 1000.0 + 0.5) as i32);
+    if let Some(sleep_drain) = sleep_drain {
+        debug_println!("[info] battery drain during sleep: {}mV", sleep_drain);
+    }
+
+    // Count the wake, so the self-test and the heartbeat are scheduled independently of the published readings
+    // Note: The counter shares a scratch register with the panic location, so it is paused while a panic is pending
+    let wakes = no_panic.then(WakeCounter::next);
+    // Note: Skipping unchanged readings always implies a heartbeat, so a stable device is not mistaken for a dead one
+    let heartbeat_every = match config {
+        Ok(config) if config.MQTT_HEARTBEAT_EVERY > 0 => config.MQTT_HEARTBEAT_EVERY as u32,
+        Ok(config) if config.SENSOR_DELTA > 0 => HEARTBEAT_FALLBACK_EVERY,
+        _ => 0,
+    };
+    let heartbeat = wakes.filter(|wakes| heartbeat_every > 0 && wakes.is_multiple_of(heartbeat_every));
+
+    // Start a deep-sleep self-test about once per day after a regular cycle
+    // Note: The self-test cycle sleeps right after the reading without booting the radio, so the voltage delta only
+    //  covers the sleep itself
+    if let (Ok(config), Some(battery), Some(wakes)) = (config, battery, wakes)
+        && lifecycle_before_reset == Some(Lifecycle::LIGHTSLEEP)
+        && sleep_drain.is_none()
+        && SleepProbe::is_due(wakes, config.SENSOR_SLEEP_SECS)
+    {
+        // Persist the battery voltage and sleep until the next cycle
+        SleepProbe::store(battery);
+        PanicCounter::reset();
+        Lifecycle::store(Lifecycle::LIGHTSLEEP);
+        debug_println!("[info] entering sleep for deep-sleep self-test");
+        return ControlFlow::Break(align_sleep(schedule, config.SENSOR_SLEEP_SECS));
+    }
+
+    // Accumulate the sensor voltage over multiple wakes, and only publish the average once enough readings are collected
+    // Note: Like the self-test, accumulating cycles sleep right after the reading without booting the radio. The
+    //  accumulator shares a scratch register with the panic location, so it is skipped while a panic is pending
+    if let (Ok(config), Ok(readings)) = (config, &mut *readings)
+        && config.SENSOR_AVERAGE > 1
+        && no_panic
+    {
+        // Add the reading to the running sum
+        let average = ReadingAverage::load().unwrap_or_default().add(LastReading::quantize(readings.sensor));
+        if average.count < config.SENSOR_AVERAGE
+            && lifecycle_before_reset == Some(Lifecycle::LIGHTSLEEP)
+            && sleep_drain.is_none()
+            && heartbeat.is_none()
+        {
+            // Persist the running sum and sleep until the next cycle
+            average.store();
+            PanicCounter::reset();
+            Lifecycle::store(Lifecycle::LIGHTSLEEP);
+            debug_println!(
+                "[info] entering sleep after accumulating reading {}/{}",
+                average.count,
+                config.SENSOR_AVERAGE
+            );
+            return ControlFlow::Break(align_sleep(schedule, config.SENSOR_SLEEP_SECS));
+        }
+
+        // Publish the average instead of the single reading
+        // Note: An average may be published early if a self-test result or a heartbeat is due, or after an unclean
+        //  reset
+        ReadingAverage::clear();
+        readings.sensor = average.voltage();
+        debug_println!("[info] averaged {} sensor readings", average.count);
+    }
+
+    // Skip the radio entirely if the reading has not changed significantly and no heartbeat is due
+    // Note: Like the self-test, this only follows a regular cycle, and is skipped while a panic is pending, so a crash
+    //  is still announced
+    let millivolts = readings.as_ref().ok().map(|readings| LastReading::quantize(readings.sensor));
+    if let (Ok(config), Some(millivolts), Some(last_reading)) = (config, millivolts, LastReading::load())
+        && config.SENSOR_DELTA > 0
+        && millivolts.abs_diff(last_reading.millivolts) <= config.SENSOR_DELTA
+        && lifecycle_before_reset == Some(Lifecycle::LIGHTSLEEP)
+        && no_panic
+        && heartbeat.is_none()
+        && sleep_drain.is_none()
+    {
+        // Keep the published value as reference so slow drifts are not missed, and sleep until the next cycle
+        PanicCounter::reset();
+        Lifecycle::store(Lifecycle::LIGHTSLEEP);
+        debug_println!("[info] entering sleep after unchanged reading: {}mV", millivolts);
+        return ControlFlow::Break(align_sleep(schedule, config.SENSOR_SLEEP_SECS));
+    }
+    ControlFlow::Continue(Wake { schedule, rtc_present, sleep_drain, heartbeat, millivolts })
+}
+
+/// Tries to announce the crash after the previous app has not stopped gracefully while signaling it, then resets or
+/// holds the device in low-power sleep; see [`panic::after_panic`]
+///
+/// # Note
+/// The network may be the cause of the panic, so the crash notice is strictly best-effort with a tight timeout.
+async fn after_panic<B>(
+    board: B,
+    radio: &Cyw43Session,
+    network: Stack<'static>,
+    led: &StatusLedSession,
+    config: &Result<AppConfig, ConfigError>,
+    device_id: DeviceId,
+    last_panic: Option<&[u8]>,
+) -> !
+where
+    B: Board,
+{
+    /// The time budget for the best-effort crash notice after a panic
+    const CRASH_NOTICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    let notice = async {
+        let Ok(config) = config else {
+            // We cannot connect without a valid config
+            return;
+        };
+        let publish = async {
+            // Join the network
+            if let Err(error) = radio.join_best(config).await {
+                debug_println!("[warn] {}", error);
+                return;
+            }
+            network.wait_link_up().await;
+            network.wait_config_up().await;
+
+            // Publish the panic location if known
+            let location = last_panic.unwrap_or(b"unknown");
+            match MqttStack::<MQTT_BUF_SIZE>::new(network).publish_once(config, device_id, "crash", location).await {
+                Ok(()) => debug_println!("[info] published crash notice"),
+                Err(error) => debug_println!("[warn] failed to publish crash notice: {}", error),
+            }
+        };
+        if with_timeout(CRASH_NOTICE_TIMEOUT, publish).await.is_err() {
+            debug_println!("[warn] timed out publishing crash notice");
+        }
+    };
+    let hold_duration = panic::after_panic(led, notice).await;
+    radio.shutdown().await;
+
+    // Hold the device in low-power sleep as it keeps panicking
+    debug_println!("[info] entering hold sleep after repeated panics");
+    board.reset_after(hold_duration).await
+}
+
+/// Joins the network via the strongest access point and waits for link and DHCP, and returns the signal strength from
+/// the join scan and the assigned IP address
+async fn connect_network<B>(
+    board: &B,
+    radio: &Cyw43Session,
+    network: Stack<'static>,
+    config: &AppConfig,
+    led: &StatusLedSession,
+) -> Result<(i32, Option<Ipv4Address>), Abort>
+where
+    B: Board,
+{
+    // Try to join network via the strongest access point
+    let access_point = match radio.join_best(config).await {
+        Ok(access_point) => access_point,
+        Err(error) => {
+            // Signal the error to the user
+            debug_println!("[warn] {}", error);
+            led.set(StatusLedMode::BlinkSlow);
+            Timer::after(ERROR_SIGNAL_DURATION).await;
+            return Err(Abort::Retry("wifi error"));
+        }
+    };
+    debug_println!("[info] joined wifi: {}", config.WIFI_SSID);
+
+    // Wait for link and DHCP
+    // Note: The timeout is budgeted from the remaining watchdog time, so there is still enough time for the MQTT cycle
+    let network_timeout = board.remaining().checked_sub(CYCLE_BUDGET).unwrap_or(Duration::MIN);
+    let network_up = async {
+        // Wait for link
+        network.wait_link_up().await;
+        debug_println!("[info] got network link");
+
+        // Wait for DHCP
+        network.wait_config_up().await;
+        debug_println!("[info] got dhcp config");
+    };
+    let Ok(()) = with_timeout(network_timeout, network_up).await else {
+        // Signal the error to the user
+        debug_println!("[warn] timed out waiting for network link or dhcp config");
+        led.set(StatusLedMode::BlinkSlow);
+        Timer::after(ERROR_SIGNAL_DURATION).await;
+        return Err(Abort::Retry("network timeout"));
+    };
+
+    // Go back to sleep if joining the network has already used up the awake budget
+    // Note: The error signal is skipped, as we are already out of time
+    if AwakeBudget::is_exhausted() {
+        return Err(Abort::Retry("exhausting the awake budget"));
+    }
+
+    // Take the assigned IP address
+    let ip = network.config_v4().map(|config| config.address.address());
+    debug_println!("[info] ip address: {:?}", ip);
+    Ok((access_point.rssi, ip))
+}
+
+/// Runs the MQTT cycle, retries it on transient failures as long as the watchdog budget allows it, and mirrors the
+/// readout to the secondary MQTT server; returns whether the config has been updated at runtime
+async fn mqtt_cycle<B, const N: usize>(
+    board: &B,
+    mqtt_stack: &mut MqttStack<N>,
+    config: &mut AppConfig,
+    led: &StatusLedSession,
+    flash: &mut Peri<'static, FLASH>,
+    report: &Report,
+    last_panic: Option<LastPanic>,
+) -> Result<bool, Abort>
+where
+    B: Board,
+{
+    /// The maximum amount of MQTT cycle attempts within a single wake cycle
+    const CYCLE_ATTEMPTS: usize = 3;
+
+    // Init MQTT stack, and divert to long sleep if the server address is malformed
+    let mqtt = match mqtt_stack.init(config, report.device_id) {
+        Ok(mqtt) => mqtt,
+        Err(error) => {
+            // Signal the invalid address to the user
+            debug_println!("[warn] {}", error);
+            led.set(StatusLedMode::BlinkDouble);
+            Timer::after(ERROR_SIGNAL_DURATION).await;
+            return Err(Abort::Rejected("mqtt address error"));
+        }
+    };
+
+    // Check for buffered readings from previous cycles
+    let backlog = !History::new(flash.reborrow()).is_empty();
+    let mut state = CycleState { born: false, backlog, maintenance: false, config_changed: false, last_panic };
+
+    // Run the MQTT cycle, and retry it on transient failures
+    for attempt in 1.. {
+        match mqtt_session(board, &mqtt, config, led, flash, report, &mut state).await {
+            Ok(()) => break,
+            Err(error) if error.is_config_error() => {
+                // Signal the config error to the user, and sleep longer
+                debug_println!("[warn] {}", error);
+                led.set(StatusLedMode::BlinkTriple);
+                Timer::after(ERROR_SIGNAL_DURATION).await;
+                return Err(Abort::Rejected("mqtt config error"));
+            }
+            Err(MqttError::OverBudget) => {
+                // Skip the remaining messages and the error signal, as we are already out of time
+                debug_println!("[warn] {}", MqttError::OverBudget);
+                return Err(Abort::Retry("exhausting the awake budget"));
+            }
+            Err(error) if attempt < CYCLE_ATTEMPTS && board.remaining() > CYCLE_BUDGET => {
+                // Log warning and retry
+                debug_println!("[warn] {}; retrying", error);
+                led.set(StatusLedMode::On);
+            }
+            Err(error) => {
+                // Signal the error to the user
+                debug_println!("[warn] {}", error);
+                led.set(StatusLedMode::BlinkSlow);
+                Timer::after(ERROR_SIGNAL_DURATION).await;
+
+                // Fail over to the secondary MQTT server if configured
+                mirror_readout(mqtt_stack, config, report, board.remaining(), false).await;
+                return Err(Abort::Retry("mqtt error"));
+            }
+        }
+    }
+
+    // Record the published reading for the next cycle, and mirror the readout to the secondary MQTT server
+    if let Some(millivolts) = report.millivolts {
+        LastReading { millivolts }.store();
+    }
+    mirror_readout(mqtt_stack, config, report, board.remaining(), true).await;
+    Ok(state.config_changed)
+}
+
+/// Runs a single MQTT session: Connects to the MQTT server, applies retained runtime config updates and checks for
+/// firmware updates, publishes the buffered readings and the report, stays connected while maintenance mode is
+/// requested, and disconnects
+async fn mqtt_session<'a, B, const N: usize>(
+    board: &B,
+    mqtt: &'a MqttClient<'a, N>,
+    config: &mut AppConfig,
+    led: &StatusLedSession,
+    flash: &mut Peri<'static, FLASH>,
+    report: &Report,
+    state: &mut CycleState,
+) -> Result<(), MqttError>
+where
+    B: Board,
+{
+    /// The time to wait for retained config updates
+    const CONFIG_UPDATE_TIMEOUT: Duration = Duration::from_secs(1);
+    /// The heartbeat interval while in maintenance mode
+    const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10);
+
+    // Connect to MQTT server and establish MQTT session
+    let mqtt = mqtt.connect().await?;
+    debug_println!("[info] connected to mqtt server");
+    let mut mqtt = mqtt.login().await?;
+    debug_println!("[info] established mqtt session");
+    led.set(StatusLedMode::Breathe);
+
+    // Report availability if configured
+    if !config.MQTT_AVAIL.is_empty() {
+        mqtt.publish_retained(config.MQTT_AVAIL, b"online").await?;
+    }
+
+    // Announce this boot once, even if the cycle is retried
+    if !state.born {
+        let birth =
+            BirthInfo { version: report.version, reset: report.reset_reason, seq: report.sequence, ip: report.ip };
+        mqtt.birth(&birth).await?;
+        debug_println!("[info] published birth message");
+        state.born = true;
+    }
+
+    // Apply retained runtime config updates and check for firmware updates
+    let mut ota = report.firmware_version.map(OtaClient::new);
+    let (config_topic, ota_topic) = (mqtt.topic("config/set")?, mqtt.topic(OtaClient::TOPIC)?);
+    let maintenance_topic = mqtt.topic("maintenance")?;
+    mqtt.subscribe("config/set").await?;
+    if ota.is_some() {
+        mqtt.subscribe(OtaClient::TOPIC).await?;
+    }
+    mqtt.subscribe("maintenance").await?;
+    while let Some(message) = mqtt.next_message(CONFIG_UPDATE_TIMEOUT).await {
+        if let Some(ota) = &mut ota
+            && message.topic() == &*ota_topic
+            && ota.check(message.payload())
+        {
+            debug_println!("[info] firmware update available: {:?}", ota.available());
+        }
+        if message.topic() == &*config_topic && config.apply(message.payload()) {
+            debug_println!("[info] applied runtime config update");
+            state.config_changed = true;
+        }
+        if message.topic() == &*maintenance_topic {
+            state.maintenance = message.payload() == b"true";
+        }
+    }
+    if let Some(version) = ota.as_ref().and_then(OtaClient::available) {
+        // Signal the available update to the user
+        led.set(StatusLedMode::Blink);
+        mqtt.publish_metric("ota/pending", version).await?;
+        debug_println!("[info] published pending firmware update: {}", version);
+    }
+
+    // Publish buffered readings before the current one, but only once, even if the cycle is retried
+    if state.backlog {
+        let published = publish_backlog(&mut mqtt, &mut History::new(flash.reborrow())).await?;
+        debug_println!("[info] published {} buffered readings", published);
+        state.backlog = false;
+    }
+
+    // Publish the report
+    publish_report(&mut mqtt, config, report, &mut state.last_panic).await?;
+
+    // Stay connected while maintenance mode is requested, so the device can be serviced on-site
+    // Note: The sensors are not read again, as the battery is sampled via the radio clock pin
+    if state.maintenance {
+        debug_println!("[info] entering maintenance mode");
+        let deadline = Instant::now() + config.MAINTENANCE_SECS;
+        while state.maintenance && Instant::now() < deadline {
+            // Keep the watchdog fed and report that we are still awake
+            board.set_timeout(APP_TIMEOUT);
+            mqtt.publish_metric("awake_ms", Instant::now().as_millis()).await?;
+
+            // Apply runtime config updates until the next heartbeat, and leave once the request is cleared
+            let heartbeat = Instant::now() + MAINTENANCE_INTERVAL;
+            while let Some(message) = mqtt.next_message(heartbeat.saturating_duration_since(Instant::now())).await {
+                if message.topic() == &*config_topic && config.apply(message.payload()) {
+                    debug_println!("[info] applied runtime config update");
+                    state.config_changed = true;
+                }
+                if message.topic() == &*maintenance_topic {
+                    state.maintenance = message.payload() == b"true";
+                }
+            }
+        }
+        if state.maintenance {
+            // Clear the retained request, so a forgotten request does not keep the device awake every cycle
+            mqtt.publish_retained("maintenance", b"").await?;
+            state.maintenance = false;
+            debug_println!("[info] maintenance mode timed out");
+        }
+        debug_println!("[info] leaving maintenance mode");
+    }
+
+    // Report unavailability and disconnect
+    // Note: The will only covers ungraceful disconnects, so we report the clean disconnect ourselves
+    if !config.MQTT_AVAIL.is_empty() {
+        mqtt.publish_retained(config.MQTT_AVAIL, b"offline").await?;
+    }
+    mqtt.disconnect().await?;
+    debug_println!("[info] disconnected from mqtt server");
+    Ok(())
+}
+
+/// Publishes the readings and the diagnostics of the given report, and omits unavailable values; the pending panic
+/// location is published and cleared as well
+///
+/// # Note
+/// The readings are either published as single structured readout, or as one plain text topic per value. The raw
+/// counts, the statistics and the health summary are always structured, so they use JSON unless CBOR has been selected
+/// explicitly.
+async fn publish_report<T, const N: usize>(
+    mqtt: &mut MqttSession<T, N>,
+    config: &AppConfig,
+    report: &Report,
+    last_panic: &mut Option<LastPanic>,
+) -> Result<(), MqttError>
+where
+    T: Reconnect,
+{
+    // Scope the MQTT buffers due to stack size
+    if config.MQTT_FORMAT != MqttFormat::Text
+        && let Some(readout) = report.readout(config.MQTT_FORMAT)
+    {
+        // Publish the entire readout as structured payload
+        // Note: An oversized payload is skipped, so the remaining topics are still published
+        let published = match readout {
+            Ok(payload) => mqtt.publish("readout", &payload).await,
+            Err(error) => Err(error),
+        };
+        match published {
+            Ok(()) => debug_println!("[info] published sensor readout as {}", config.MQTT_FORMAT),
+            Err(error) if error.is_packet_error() => debug_println!("[warn] skipped sensor readout: {}", error),
+            Err(error) => return Err(error),
+        }
+    }
+    if config.MQTT_FORMAT == MqttFormat::Text {
+        // Publish the readings as plain text values, where the voltages and the chip temperature use the configured
+        // decimals
+        let (readings, air_readings) = (report.readings.as_ref().ok(), report.air_readings.as_ref().ok());
+        let decimals = Some(config.SENSOR_DECIMALS);
+        let text_values = [
+            ("voltage", "sensor voltage", readings.map(|readings| readings.sensor), decimals),
+            ("temperature", "system temperature", readings.and_then(|readings| readings.temperature), decimals),
+            ("battery", "battery voltage", readings.map(|readings| readings.battery), decimals),
+            ("air_temp", "air temperature", air_readings.map(|air_readings| air_readings.temperature), None),
+            ("humidity", "air humidity", air_readings.map(|air_readings| air_readings.humidity), None),
+            ("soil_temp", "soil temperature", report.soil_temperature.as_ref().ok().copied(), None),
+        ];
+        for (topic, description, value, decimals) in text_values {
+            let Some(value) = value else {
+                // Omit unavailable values
+                continue;
+            };
+            match decimals {
+                Some(decimals) => {
+                    mqtt.publish(topic, &MqttBuffer::<VALUE_BUF_SIZE>::from_float(value, decimals)).await?
+                }
+                None => mqtt.publish_metric(topic, value).await?,
+            }
+            debug_println!("[info] published {}: {}", description, value);
+        }
+
+        // Publish reading sequence number
+        mqtt.publish_metric("seq", report.sequence).await?;
+        debug_println!("[info] published reading sequence number: {}", report.sequence);
+    }
+    if let Ok(readings) = &report.readings {
+        // Publish the raw ADC counts for calibration, and omit the temperature if it is not sampled
+        let mut raw = Payload::<VALUE_BUF_SIZE>::new(config.MQTT_FORMAT.structured());
+        raw.unsigned("sensor", readings.sensor_raw as u64);
+        if let Some(temperature_raw) = readings.temperature_raw {
+            raw.unsigned("temperature", temperature_raw as u64);
+        }
+        mqtt.publish("raw", &raw.finish()?).await?;
+        debug_println!("[info] published raw adc counts: {}", readings.sensor_raw);
+    }
+    if let Ok(readings) = &report.readings
+        && let Some(stats) = readings.stats
+    {
+        // Publish the sensor sample statistics, so electrical noise or a bad probe can be diagnosed
+        let mut statistics = Payload::<PAYLOAD_BUF_SIZE>::new(config.MQTT_FORMAT.structured());
+        statistics.float("min", stats.min).float("max", stats.max).float("stddev", stats.stddev);
+        mqtt.publish("stats", &statistics.finish()?).await?;
+        debug_println!("[info] published sensor standard deviation: {}", stats.stddev);
+    }
+    if let Ok(readings) = &report.readings
+        && (config.SENSOR_FAULT_LOW > 0 || config.SENSOR_FAULT_HIGH > 0)
+    {
+        // Publish the plausibility state, so a loose or shorted probe is noticed
+        mqtt.publish_metric("sensor_fault", readings.suspect).await?;
+        debug_println!("[info] published sensor fault state: {}", readings.suspect);
+    }
+    if let Some(wakes) = report.heartbeat {
+        // Publish the heartbeat, so consumers can tell an unchanged reading from a missing device
+        mqtt.publish_metric("heartbeat", wakes).await?;
+        debug_println!("[info] published heartbeat: {}", wakes);
+    }
+    if let Some(reason) = report.alert {
+        // Publish threshold alert state, and the reason if the sensor voltage is out of range
+        mqtt.publish_metric("alert", reason.is_some()).await?;
+        if let Some(reason) = reason {
+            mqtt.publish("alert_reason", reason.as_bytes()).await?;
+        }
+        debug_println!("[info] published sensor alert: {:?}", reason);
+    }
+    if let Err(error) = &report.air_readings
+        && !matches!(error, AirSensorError::Missing)
+    {
+        // Publish air sensor error and continue with the cycle
+        // Note: A missing sensor is not an error, as the air sensor is optional
+        mqtt.publish_metric("air_sensor_error", error).await?;
+        debug_println!("[warn] published air sensor error: {}", error);
+    }
+    if let Err(error) = &report.soil_temperature
+        && !matches!(error, OneWireError::Missing)
+    {
+        // Publish soil thermometer error and continue with the cycle
+        // Note: A missing sensor is not an error, as the soil thermometer is optional
+        mqtt.publish_metric("soil_temp_error", error).await?;
+        debug_println!("[warn] published soil thermometer error: {}", error);
+    }
+    if let Err(error) = &report.readings {
+        // Publish sensor error and continue with the cycle
+        mqtt.publish_metric("sensor_error", error).await?;
+        debug_println!("[warn] published sensor error: {}", error);
+    }
+    if let Some(timestamp) = report.timestamp {
+        // Publish reading timestamp
+        mqtt.publish_metric("timestamp", timestamp).await?;
+        debug_println!("[info] published reading timestamp: {}", timestamp);
+    }
+    {
+        // Publish wifi signal strength
+        mqtt.publish_metric("rssi", report.rssi).await?;
+        debug_println!("[info] published wifi signal strength: {}", report.rssi);
+    }
+    if let Some(ip) = report.ip {
+        // Publish IP address
+        mqtt.publish_metric("ip", ip).await?;
+        debug_println!("[info] published ip address: {}", ip);
+    }
+    {
+        // Publish firmware version
+        mqtt.publish("version", report.version.as_bytes()).await?;
+        debug_println!("[info] published firmware version: {}", report.version);
+    }
+    {
+        // Publish device ID
+        mqtt.publish_metric("deviceid", report.device_id).await?;
+        debug_println!("[info] published device id: {}", report.device_id);
+    }
+    if let Some(location) = last_panic {
+        // Publish and clear last panic location
+        mqtt.publish("lastpanic", location).await?;
+        LastPanic::clear();
+        *last_panic = None;
+        debug_println!("[info] published last panic location");
+    }
+    {
+        // Publish awake time so far
+        // Note: The instant starts at zero on boot, and the remaining shutdown sequence only takes a second or so
+        let awake_ms = Instant::now().as_millis();
+        mqtt.publish_metric("awake_ms", awake_ms).await?;
+        debug_println!("[info] published awake time: {}ms", awake_ms);
+    }
+    {
+        // Publish the stack high-water mark
+        let stack_free = stack::free();
+        mqtt.publish_metric("stack_free", stack_free).await?;
+        debug_println!("[info] published free stack: {} bytes", stack_free);
+    }
+    if let Some(sleep_drain) = report.sleep_drain {
+        // Publish the deep-sleep self-test result
+        mqtt.publish_metric("sleep_drain", sleep_drain).await?;
+        debug_println!("[info] published battery drain during sleep: {}mV", sleep_drain);
+    }
+    {
+        // Publish the health summary
+        // Note: An oversized summary is skipped, as it is not worth to abort the cycle for diagnostics
+        let mut health = Payload::<PAYLOAD_BUF_SIZE>::new(config.MQTT_FORMAT.structured());
+        health.text("reset", report.reset_reason).unsigned("awake_ms", Instant::now().as_millis());
+        health.unsigned("stack_free", stack::free() as u64).unsigned("starved", report.starved as u64);
+        health.signed("rssi", report.rssi as i64);
+        if let Ok(readings) = &report.readings {
+            health.float("battery", readings.battery);
+        }
+        if let Some(sleep_drain) = report.sleep_drain {
+            health.signed("sleep_drain", sleep_drain as i64);
+        }
+        let published = match health.finish() {
+            Ok(payload) => mqtt.publish("health", &payload).await,
+            Err(error) => Err(error),
+        };
+        match published {
+            Ok(()) => {
+                // Clear the watchdog starvation count, as it has been reported now
+                Starvation::reset();
+                debug_println!("[info] published health summary");
+            }
+            Err(error) if error.is_packet_error() => debug_println!("[warn] skipped health summary: {}", error),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/// Mirrors the readout to the secondary MQTT server if configured, and logs whether it has been delivered to the
+/// primary MQTT server as well; see [`publish_secondary`]
+///
+/// # Note
+/// The readout is always structured, so it uses JSON unless CBOR has been selected explicitly.
+async fn mirror_readout<const N: usize>(
+    mqtt: &mut MqttStack<N>,
+    config: &AppConfig,
+    report: &Report,
+    remaining: Duration,
+    primary: bool,
+) {
+    if let Some(secondary) = config.secondary()
+        && let Some(Ok(payload)) = report.readout(config.MQTT_FORMAT.structured())
+    {
+        let mirrored = publish_secondary(mqtt, &secondary, report.device_id, &payload, remaining).await;
+        debug_println!("[info] readout delivered to primary: {}, secondary: {}", primary, mirrored);
+    }
+}
+
+/// Shuts down the radio, clears the buffered readings and persists runtime config updates, then sleeps until the next
+/// cycle
+async fn shutdown<B>(
+    board: B,
+    radio: &Cyw43Session,
+    mut flash: Peri<'static, FLASH>,
+    config: &AppConfig,
+    config_changed: bool,
+    schedule: Option<WallClock>,
+) -> !
+where
+    B: Board,
+{
+    // Shutdown radio (also turns LED off)
+    radio.shutdown().await;
+    debug_println!("[info] stopped radio");
+
+    // Clear the buffered readings, as they have been published now
+    // Note: This must happen after the radio has been stopped, as the flash is unavailable during the erase
+    History::new(flash.reborrow()).clear();
+
+    // Persist runtime config updates
+    // Note: This must happen after the radio has been stopped, as the flash is unavailable during the write
+    if config_changed {
+        match config.store(flash) {
+            Ok(()) => debug_println!("[info] stored updated config"),
+            Err(error) => debug_println!("[warn] failed to store updated config: {}", error),
+        }
+    }
+
+    // Sleep and perform reset
+    PanicCounter::reset();
+    Lifecycle::store(Lifecycle::LIGHTSLEEP);
+    debug_println!("[info] entering sleep after {}ms awake", Instant::now().as_millis());
+    board.reset_after(align_sleep(schedule, config.SENSOR_SLEEP_SECS)).await
+}
+
+/// Shuts down the radio and buffers the given reading if any, so it can be published once connectivity returns, then
+/// sleeps for the given duration
+///
+/// # Note
+/// This must only be called after the radio has been booted, as the flash is unavailable while the radio is active.
+async fn sleep_after_error<B>(
+    board: B,
+    radio: &Cyw43Session,
+    flash: Peri<'_, FLASH>,
+    millivolts: Option<u16>,
+    config: &AppConfig,
+    sleep: Duration,
+    reason: &str,
+) -> !
+where
+    B: Board,
+{
+    // Shutdown radio (also turns LED off)
+    radio.shutdown().await;
+
+    // Buffer the reading
+    // Note: This must happen after the radio has been stopped, as the flash is unavailable during the write
+    if let Some(millivolts) = millivolts {
+        History::new(flash).push(millivolts, config.SENSOR_SLEEP_SECS);
+        debug_println!("[info] buffered reading for later upload");
+    }
+
+    // Sleep until the next cycle
+    Lifecycle::store(Lifecycle::LIGHTSLEEP);
+    debug_println!("[info] entering sleep for {}s after {}", sleep.as_secs(), reason);
+    board.reset_after(sleep).await
+}
+
+/// Publishes the given readout to the secondary MQTT server, and returns whether the readout has been published
+///
+/// # Note
+/// The secondary server is strictly best-effort: Only the readout is mirrored, without availability, runtime config
+/// updates or buffered readings, and it is skipped if the awake budget or the remaining watchdog budget is too tight.
+async fn publish_secondary<const N: usize>(
+    mqtt: &mut MqttStack<N>,
+    secondary: &AppConfig,
+    device_id: DeviceId,
+    readout: &[u8],
+    remaining: Duration,
+) -> bool {
+    /// The time budget for the secondary server
+    const SECONDARY_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Skip the secondary server if we are out of time
+    if AwakeBudget::is_exhausted() || remaining <= SECONDARY_TIMEOUT {
+        debug_println!("[warn] skipped secondary mqtt server due to time budget");
+        return false;
+    }
+
+    // Connect, publish and disconnect within the time budget
+    match with_timeout(SECONDARY_TIMEOUT, mqtt.publish_once(secondary, device_id, "readout", readout)).await {
+        Ok(Ok(())) => {
+            debug_println!("[info] published readout to secondary mqtt server");
+            true
+        }
+        Ok(Err(error)) => {
+            debug_println!("[warn] failed to publish readout to secondary mqtt server: {}", error);
+            false
+        }
+        Err(_) => {
+            debug_println!("[warn] timed out publishing readout to secondary mqtt server");
+            false
+        }
+    }
+}
+
+/// Publishes the buffered readings in batches of comma-separated `<age_secs>:<millivolts>` pairs to the `history` topic,
+/// oldest first, and returns the amount of published readings
+///
+/// # Note
+/// The readings have been taken without a wall clock, so their age is relative to the current reading and accumulated
+/// from the sleep durations in between. Corrupt entries are skipped.
+async fn publish_backlog<T, const N: usize>(
+    mqtt: &mut MqttSession<T, N>,
+    history: &mut History<'_>,
+) -> Result<usize, MqttError>
+where
+    T: Reconnect,
+{
+    /// The buffer size for a single batch
+    const BATCH_BUF_SIZE: usize = 512;
+    /// The buffer size for a single pair
+    const PAIR_BUF_SIZE: usize = 24;
+
+    // Compute the age of the oldest reading
+    let len = history.len();
+    let mut age: u64 = (0..len).filter_map(|index| history.get(index)).map(|entry| entry.gap_secs as u64).sum();
+
+    // Collect the pairs into batches, and publish every full batch
+    let (mut batch, mut published) = (MqttBuffer::<BATCH_BUF_SIZE>::new(), 0);
+    for entry in (0..len).filter_map(|index| history.get(index)) {
+        // Format the pair, and advance the age to the next reading
+        let pair = MqttBuffer::<PAIR_BUF_SIZE>::from_display(format_args!("{}:{}", age, entry.millivolts));
+        age -= entry.gap_secs as u64;
+
+        // Publish the batch if the pair does not fit anymore
+        if batch.len() + pair.len() + 1 > BATCH_BUF_SIZE {
+            mqtt.publish("history", &batch).await?;
+            batch = MqttBuffer::new();
+        }
+        if !batch.is_empty() {
+            batch.try_extend(b",").expect("history batch is too large");
+        }
+        batch.try_extend(&pair).expect("history batch is too large");
+        published += 1;
+    }
+
+    // Publish the last batch
+    if !batch.is_empty() {
+        mqtt.publish("history", &batch).await?;
+    }
+    Ok(published)
+}
@@ -0,0 +1,40 @@
+//! Soft awake-time budget that is enforced independently of the watchdog
+
+use crate::debug_println;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Instant, Timer};
+
+/// Whether the awake budget is exhausted
+static EXHAUSTED: AtomicBool = AtomicBool::new(false);
+
+/// The awake budget
+///
+/// # Note
+/// Unlike the watchdog, the budget does not reset the device; instead, the main flow polls it at each step and takes
+/// the regular way to sleep once it is exhausted, so a slow server cannot burn the entire watchdog budget.
+#[derive(Debug, Clone, Copy)]
+pub struct AwakeBudget;
+impl AwakeBudget {
+    /// Starts the budget task, which flags the budget as exhausted once the device has been awake for the given
+    /// duration since boot, or does nothing if the duration is zero
+    pub fn start(budget: Duration, spawner: &Spawner) {
+        if budget > Duration::MIN {
+            spawner.must_spawn(awake_budget_task(Instant::MIN + budget));
+        }
+    }
+
+    /// Whether the awake budget is exhausted
+    pub fn is_exhausted() -> bool {
+        EXHAUSTED.load(Ordering::SeqCst)
+    }
+}
+
+/// [`AwakeBudget`] task
+#[embassy_executor::task]
+async fn awake_budget_task(deadline: Instant) {
+    // Note: The instant starts at zero on boot, so the deadline is an absolute awake time
+    Timer::at(deadline).await;
+    EXHAUSTED.store(true, Ordering::SeqCst);
+    debug_println!("[warn] awake budget exhausted after {}ms", Instant::now().as_millis());
+}
@@ -0,0 +1,927 @@
+//! Configuration provider
+
+use crate::debug::StatusLed;
+use crate::lifecycle::ReadingAverage;
+use crate::mqtt_codec::{DECIMALS_MAX, MqttBuffer};
+use crate::payload::MqttFormat;
+use crate::readout::TemperatureUnit;
+use crate::sensor::{FAULT_HIGH_MILLIVOLTS, FAULT_LOW_MILLIVOLTS, SAMPLE_COUNT, VREF_MILLIVOLTS, WARMUP_SAMPLES};
+use crate::{APP_TIMEOUT, debug_println};
+use core::fmt::{Display, Formatter};
+use embassy_rp::Peri;
+use embassy_rp::flash::{Blocking, FLASH_BASE, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_time::Duration;
+
+/// The flash size as configured in `memory.x`
+pub const FLASH_SIZE: usize = 2048 * 1024;
+/// The config sector size
+const USERDATA_SIZE: usize = 4096;
+
+/// Userdata section in flash
+#[unsafe(link_section = ".userdata")]
+static USERDATA: [u8; USERDATA_SIZE] = [0; USERDATA_SIZE];
+
+/// A config loading error
+#[derive(Debug, Clone, Copy)]
+pub enum ConfigError {
+    /// A required key is missing
+    Missing(&'static str),
+    /// A value could not be parsed
+    Malformed(&'static str),
+    /// The serialized config exceeds the config sector, so it has not been stored
+    TooLarge,
+}
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "missing config value: {name}"),
+            Self::Malformed(name) => write!(f, "malformed config value: {name}"),
+            Self::TooLarge => write!(f, "config is too large for the config sector"),
+        }
+    }
+}
+
+/// The WIFI authentication mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiAuth {
+    /// Open network if the password is empty, WPA2/WPA3 otherwise
+    Auto,
+    /// Open network without password
+    Open,
+    /// WPA2-PSK only
+    Wpa2,
+    /// WPA3-SAE only
+    Wpa3,
+    /// WPA2-PSK or WPA3-SAE
+    Wpa2Wpa3,
+}
+impl WifiAuth {
+    /// Parses the authentication mode from its config name
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(Self::Auto),
+            "open" => Some(Self::Open),
+            "wpa2" => Some(Self::Wpa2),
+            "wpa3" => Some(Self::Wpa3),
+            "wpa2-wpa3" => Some(Self::Wpa2Wpa3),
+            _ => None,
+        }
+    }
+}
+impl Display for WifiAuth {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Open => write!(f, "open"),
+            Self::Wpa2 => write!(f, "wpa2"),
+            Self::Wpa3 => write!(f, "wpa3"),
+            Self::Wpa2Wpa3 => write!(f, "wpa2-wpa3"),
+        }
+    }
+}
+
+/// The MQTT quality-of-service level for publishing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[allow(clippy::enum_variant_names, reason = "the variants are named after the MQTT delivery guarantees")]
+pub enum MqttQos {
+    /// At most once delivery without acknowledgement
+    AtMostOnce = 0,
+    /// At least once delivery with acknowledgement
+    AtLeastOnce = 1,
+    /// Exactly once delivery with a two-step handshake
+    ExactlyOnce = 2,
+}
+impl MqttQos {
+    /// Parses the quality-of-service level from its config name
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::AtMostOnce),
+            "1" => Some(Self::AtLeastOnce),
+            "2" => Some(Self::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+impl Display for MqttQos {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+/// The active level of the sensor power-select pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorPolarity {
+    /// The sensor is powered while the pin is high (e.g. direct supply or N-channel MOSFET)
+    ActiveHigh,
+    /// The sensor is powered while the pin is low (e.g. P-channel MOSFET)
+    ActiveLow,
+}
+impl SensorPolarity {
+    /// Parses the polarity from its config name
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "high" => Some(Self::ActiveHigh),
+            "low" => Some(Self::ActiveLow),
+            _ => None,
+        }
+    }
+}
+impl Display for SensorPolarity {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::ActiveHigh => write!(f, "high"),
+            Self::ActiveLow => write!(f, "low"),
+        }
+    }
+}
+
+/// The ADC-capable GPIO pin the moisture sensor is connected to
+///
+/// # Note
+/// GPIO 27 is the power-select pin and GPIO 29 samples the battery voltage, so they cannot be used for the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SensorPin {
+    /// GPIO 26 (ADC channel 0)
+    Gpio26 = 26,
+    /// GPIO 28 (ADC channel 2)
+    Gpio28 = 28,
+}
+impl SensorPin {
+    /// Parses the pin from its GPIO number
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "26" => Some(Self::Gpio26),
+            "28" => Some(Self::Gpio28),
+            _ => None,
+        }
+    }
+}
+impl Display for SensorPin {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+/// Applies a runtime update value from the parsed config slots, and returns whether the config has changed
+type RuntimeUpdate = fn(&mut AppConfig, &ConfigSlots) -> bool;
+
+/// Application config
+#[derive(Debug, Clone, Copy)]
+#[allow(non_snake_case)]
+pub struct AppConfig {
+    /// WIFI SSID
+    pub WIFI_SSID: &'static str,
+    /// WIFI password
+    pub WIFI_PASS: &'static str,
+    /// WIFI authentication mode
+    pub WIFI_AUTH: WifiAuth,
+    /// MQTT server address and port
+    pub MQTT_ADDR: &'static str,
+    /// Optional secondary MQTT server address and port to mirror the readout to, or empty to disable mirroring
+    pub MQTT_ADDR2: &'static str,
+    /// MQTT username
+    pub MQTT_USER: &'static str,
+    /// MQTT password
+    pub MQTT_PASS: &'static str,
+    /// MQTT topic prefix
+    pub MQTT_PRFX: &'static str,
+    /// MQTT topic template with the `{prefix}`, `{device}` and `{name}` placeholders
+    pub MQTT_TOPIC_FMT: &'static str,
+    /// MQTT keep-alive interval
+    pub MQTT_KEEPALIVE: Duration,
+    /// Optional MQTT availability topic, or empty to disable availability reporting
+    pub MQTT_AVAIL: &'static str,
+    /// Optional MQTT over WebSocket path, or empty to use plain TCP
+    pub MQTT_WS: &'static str,
+    /// MQTT payload format for sensor readouts
+    pub MQTT_FORMAT: MqttFormat,
+    /// The default MQTT quality-of-service level for publishing
+    pub MQTT_QOS: MqttQos,
+    /// Whether to reconnect once if the server drops the connection mid-session
+    pub MQTT_RECONNECT: bool,
+    /// Whether to request a clean MQTT session instead of resuming a persistent one
+    pub MQTT_CLEAN: bool,
+    /// The interval in wakes at which a heartbeat is published even if the reading is unchanged, or zero to disable the
+    /// heartbeat
+    pub MQTT_HEARTBEAT_EVERY: u16,
+    /// Optional NTP server address with optional port
+    pub NTP_SERVER: &'static str,
+    /// The sleep duration between to measurement cycles
+    pub SENSOR_SLEEP_SECS: Duration,
+    /// The minimum sensor voltage change in millivolts to publish a reading, or zero to always publish
+    pub SENSOR_DELTA: u16,
+    /// The sensor voltage in millivolts below which an alert is published, or zero to disable it
+    pub SENSOR_LOW: u16,
+    /// The sensor voltage in millivolts above which an alert is published, or zero to disable it
+    pub SENSOR_HIGH: u16,
+    /// The sensor voltage in millivolts below which a reading is implausible, or zero to disable the check
+    pub SENSOR_FAULT_LOW: u16,
+    /// The sensor voltage in millivolts above which a reading is implausible, or zero to disable the check
+    pub SENSOR_FAULT_HIGH: u16,
+    /// The active level of the sensor power-select pin
+    pub SENSOR_POLARITY: SensorPolarity,
+    /// The GPIO pin the sensor is connected to
+    pub SENSOR_PIN: SensorPin,
+    /// Whether the chip temperature is sampled alongside the sensor
+    pub SENSOR_TEMPERATURE: bool,
+    /// Whether the min, max and standard deviation of the sensor samples are published alongside the mean
+    pub SENSOR_STATS: bool,
+    /// The unit for published temperatures
+    pub SENSOR_TEMP_UNIT: TemperatureUnit,
+    /// The amount of leading ADC samples to discard per reading
+    pub SENSOR_WARMUP: u16,
+    /// The target duration of the ADC sampling run per reading, or zero for the default sample count and rate
+    pub SENSOR_SAMPLE_MS: Duration,
+    /// The amount of wakes to average the sensor voltage over before publishing it
+    pub SENSOR_AVERAGE: u16,
+    /// The ADC reference voltage in millivolts
+    pub SENSOR_VREF_MV: u16,
+    /// The amount of decimals to publish computed sensor values with
+    pub SENSOR_DECIMALS: u16,
+    /// The battery voltage in millivolts below which the device skips WiFi/MQTT and sleeps long, or zero to disable the
+    /// cutoff
+    pub BATTERY_CUTOFF_MV: u16,
+    /// The status LED toggle interval
+    pub LED_BLINK_MS: Duration,
+    /// Optional bench-mode sleep duration to skip the entire application logic if non-zero
+    pub BENCH_SLEEP_SECS: Duration,
+    /// The soft awake-time budget after which the device skips the remaining work and goes to sleep, or zero to disable
+    pub AWAKE_BUDGET_SECS: Duration,
+    /// The maximum time to stay awake in maintenance mode
+    pub MAINTENANCE_SECS: Duration,
+    /// Whether to skip WiFi/MQTT and only print readings in a loop
+    pub DRY_RUN: bool,
+}
+impl AppConfig {
+    /// Loads the config from the flash memory, and falls back to defaults for missing or malformed values
+    pub fn load() -> Self {
+        ConfigSlots::read().into_config()
+    }
+
+    /// Loads the config from the flash memory, and fails if required values are missing or malformed
+    pub fn try_load() -> Result<Self, ConfigError> {
+        // Read config and check that all required slots are set
+        let slots = ConfigSlots::read();
+        let mut error = slots.malformed.map(ConfigError::Malformed);
+        // Note: The password is only required if a WPA mode is configured explicitly, as open networks have none
+        let pass_required = !matches!(slots.wifi_auth, None | Some(WifiAuth::Auto | WifiAuth::Open));
+        let required = [
+            ("WIFI_SSID", true, slots.wifi_ssid),
+            ("WIFI_PASS", pass_required, slots.wifi_pass),
+            ("MQTT_ADDR", true, slots.mqtt_addr),
+        ];
+        for (name, required, value) in required {
+            if required && value.is_none() {
+                // Log error and keep the first one
+                debug_println!("[warn] missing config value: {}", name);
+                error = error.or(Some(ConfigError::Missing(name)));
+            }
+        }
+
+        // Fill optional slots with their defaults
+        match error {
+            Some(error) => Err(error),
+            None => Ok(slots.into_config()),
+        }
+    }
+
+    /// The keys that may be changed at runtime via [`Self::apply`], and how to apply their parsed values
+    ///
+    /// # Note
+    /// These are the sleep interval, the alert thresholds and the calibration values, which are safe to change remotely
+    /// as they can neither lock the device out of the network nor break the hardware setup.
+    const RUNTIME_KEYS: [(&'static str, RuntimeUpdate); 6] = [
+        ("SENSOR_SLEEP_SECS", |this, slots| Self::update(&mut this.SENSOR_SLEEP_SECS, slots.sensor_sleep_secs)),
+        ("SENSOR_LOW", |this, slots| Self::update(&mut this.SENSOR_LOW, slots.sensor_low)),
+        ("SENSOR_HIGH", |this, slots| Self::update(&mut this.SENSOR_HIGH, slots.sensor_high)),
+        ("SENSOR_VREF_MV", |this, slots| Self::update(&mut this.SENSOR_VREF_MV, slots.sensor_vref_mv)),
+        ("SENSOR_FAULT_LOW", |this, slots| Self::update(&mut this.SENSOR_FAULT_LOW, slots.sensor_fault_low)),
+        ("SENSOR_FAULT_HIGH", |this, slots| Self::update(&mut this.SENSOR_FAULT_HIGH, slots.sensor_fault_high)),
+    ];
+
+    /// Applies runtime updates given as INI-like key-value lines, and returns whether a value has been changed
+    ///
+    /// # Note
+    /// Only the keys in [`Self::RUNTIME_KEYS`] may be changed at runtime; all other keys are ignored.
+    pub fn apply(&mut self, updates: &[u8]) -> bool {
+        // Reject all keys which are not whitelisted
+        for (key, _) in ConfigSlots::pairs(updates) {
+            if !Self::RUNTIME_KEYS.iter().any(|(name, _)| *name == key) {
+                // Log warning and ignore
+                debug_println!("[warn] ignoring config value that cannot be changed at runtime: {}", key);
+            }
+        }
+
+        // Apply whitelisted values
+        let slots = ConfigSlots::parse(updates);
+        Self::RUNTIME_KEYS.iter().fold(false, |changed, (_, apply)| apply(self, &slots) || changed)
+    }
+
+    /// Replaces the target with the given value if it is set and differs, and returns whether the target has changed
+    fn update<T>(target: &mut T, value: Option<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        match value {
+            Some(value) if value != *target => {
+                *target = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The config for the secondary MQTT server, which is this config with the secondary address, or `None` if no
+    /// secondary server is configured
+    pub fn secondary(&self) -> Option<Self> {
+        match self.MQTT_ADDR2.is_empty() {
+            true => None,
+            false => Some(Self { MQTT_ADDR: self.MQTT_ADDR2, ..*self }),
+        }
+    }
+
+    /// Stores the config into the flash memory by replacing the config sector
+    ///
+    /// # Important
+    /// This must not be called while the radio is active, as the flash is unavailable during erase and write. Since the
+    /// string values of every loaded config point into the config sector, they must not be used after storing.
+    ///
+    /// # Note
+    /// If the serialized config exceeds the config sector, the flash is left untouched and [`ConfigError::TooLarge`] is
+    /// returned.
+    pub fn store(self, flash: Peri<'static, FLASH>) -> Result<(), ConfigError> {
+        // Serialize config
+        let image = self.serialize().map_err(|_| ConfigError::TooLarge)?;
+
+        // Replace the config sector
+        // Note: The flash driver runs the erase and write from RAM with interrupts disabled and XIP paused. The rest of
+        //  the erased sector is `0xFF`, which also terminates the config since it is not valid UTF-8.
+        let offset = USERDATA.as_ptr() as u32 - FLASH_BASE as u32;
+        let mut flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(flash);
+        flash.blocking_erase(offset, offset + USERDATA_SIZE as u32).expect("failed to erase config sector");
+        flash.blocking_write(offset, &image).expect("failed to write config sector");
+        Ok(())
+    }
+
+    /// Serializes the config into a config sector image, or fails if the image exceeds the config sector
+    fn serialize(&self) -> Result<MqttBuffer<USERDATA_SIZE>, core::fmt::Error> {
+        use core::fmt::Write;
+
+        // Serialize config
+        // Note: String values are always quoted, so they are read back exactly as-is
+        let mut image = MqttBuffer::new();
+        let strings = [
+            ("WIFI_SSID", self.WIFI_SSID),
+            ("WIFI_PASS", self.WIFI_PASS),
+            ("MQTT_ADDR", self.MQTT_ADDR),
+            ("MQTT_ADDR2", self.MQTT_ADDR2),
+            ("MQTT_USER", self.MQTT_USER),
+            ("MQTT_PASS", self.MQTT_PASS),
+            ("MQTT_PRFX", self.MQTT_PRFX),
+            ("MQTT_TOPIC_FMT", self.MQTT_TOPIC_FMT),
+            ("MQTT_AVAIL", self.MQTT_AVAIL),
+            ("MQTT_WS", self.MQTT_WS),
+            ("NTP_SERVER", self.NTP_SERVER),
+        ];
+        for (name, value) in strings {
+            writeln!(&mut image, "{name}=\"{value}\"")?;
+        }
+        writeln!(&mut image, "WIFI_AUTH={}", self.WIFI_AUTH)?;
+        writeln!(&mut image, "MQTT_FORMAT={}", self.MQTT_FORMAT)?;
+        writeln!(&mut image, "MQTT_QOS={}", self.MQTT_QOS)?;
+        writeln!(&mut image, "MQTT_RECONNECT={}", self.MQTT_RECONNECT)?;
+        writeln!(&mut image, "MQTT_CLEAN={}", self.MQTT_CLEAN)?;
+        writeln!(&mut image, "MQTT_HEARTBEAT_EVERY={}", self.MQTT_HEARTBEAT_EVERY)?;
+        writeln!(&mut image, "MQTT_KEEPALIVE={}", self.MQTT_KEEPALIVE.as_secs())?;
+        writeln!(&mut image, "SENSOR_SLEEP_SECS={}", self.SENSOR_SLEEP_SECS.as_secs())?;
+        writeln!(&mut image, "SENSOR_DELTA={}", self.SENSOR_DELTA)?;
+        writeln!(&mut image, "SENSOR_LOW={}", self.SENSOR_LOW)?;
+        writeln!(&mut image, "SENSOR_HIGH={}", self.SENSOR_HIGH)?;
+        writeln!(&mut image, "SENSOR_FAULT_LOW={}", self.SENSOR_FAULT_LOW)?;
+        writeln!(&mut image, "SENSOR_FAULT_HIGH={}", self.SENSOR_FAULT_HIGH)?;
+        writeln!(&mut image, "SENSOR_POLARITY={}", self.SENSOR_POLARITY)?;
+        writeln!(&mut image, "SENSOR_PIN={}", self.SENSOR_PIN)?;
+        writeln!(&mut image, "SENSOR_TEMPERATURE={}", self.SENSOR_TEMPERATURE)?;
+        writeln!(&mut image, "SENSOR_STATS={}", self.SENSOR_STATS)?;
+        writeln!(&mut image, "SENSOR_TEMP_UNIT={}", self.SENSOR_TEMP_UNIT)?;
+        writeln!(&mut image, "SENSOR_WARMUP={}", self.SENSOR_WARMUP)?;
+        writeln!(&mut image, "SENSOR_SAMPLE_MS={}", self.SENSOR_SAMPLE_MS.as_millis())?;
+        writeln!(&mut image, "SENSOR_AVERAGE={}", self.SENSOR_AVERAGE)?;
+        writeln!(&mut image, "SENSOR_VREF_MV={}", self.SENSOR_VREF_MV)?;
+        writeln!(&mut image, "SENSOR_DECIMALS={}", self.SENSOR_DECIMALS)?;
+        writeln!(&mut image, "BATTERY_CUTOFF_MV={}", self.BATTERY_CUTOFF_MV)?;
+        writeln!(&mut image, "LED_BLINK_MS={}", self.LED_BLINK_MS.as_millis())?;
+        writeln!(&mut image, "BENCH_SLEEP_SECS={}", self.BENCH_SLEEP_SECS.as_secs())?;
+        writeln!(&mut image, "AWAKE_BUDGET_SECS={}", self.AWAKE_BUDGET_SECS.as_secs())?;
+        writeln!(&mut image, "MAINTENANCE_SECS={}", self.MAINTENANCE_SECS.as_secs())?;
+        writeln!(&mut image, "DRY_RUN={}", self.DRY_RUN)?;
+        Ok(image)
+    }
+}
+
+/// Raw config slots as parsed from an INI-like config
+#[derive(Debug, Default)]
+struct ConfigSlots<'a> {
+    /// WIFI SSID
+    wifi_ssid: Option<&'a str>,
+    /// WIFI password
+    wifi_pass: Option<&'a str>,
+    /// WIFI authentication mode
+    wifi_auth: Option<WifiAuth>,
+    /// MQTT server address and port
+    mqtt_addr: Option<&'a str>,
+    /// Secondary MQTT server address and port
+    mqtt_addr2: Option<&'a str>,
+    /// MQTT username
+    mqtt_user: Option<&'a str>,
+    /// MQTT password
+    mqtt_pass: Option<&'a str>,
+    /// MQTT topic prefix
+    mqtt_prfx: Option<&'a str>,
+    /// MQTT topic template
+    mqtt_topic_fmt: Option<&'a str>,
+    /// MQTT keep-alive interval
+    mqtt_keepalive: Option<Duration>,
+    /// MQTT availability topic
+    mqtt_avail: Option<&'a str>,
+    /// MQTT over WebSocket path
+    mqtt_ws: Option<&'a str>,
+    /// MQTT payload format
+    mqtt_format: Option<MqttFormat>,
+    /// MQTT quality-of-service level
+    mqtt_qos: Option<MqttQos>,
+    /// Whether to reconnect on a dropped connection
+    mqtt_reconnect: Option<bool>,
+    /// Whether to request a clean session
+    mqtt_clean: Option<bool>,
+    /// The heartbeat interval in wakes
+    mqtt_heartbeat_every: Option<u16>,
+    /// NTP server address
+    ntp_server: Option<&'a str>,
+    /// The sleep duration between to measurement cycles
+    sensor_sleep_secs: Option<Duration>,
+    /// The minimum sensor voltage change in millivolts
+    sensor_delta: Option<u16>,
+    /// The lower sensor voltage alert threshold in millivolts
+    sensor_low: Option<u16>,
+    /// The upper sensor voltage alert threshold in millivolts
+    sensor_high: Option<u16>,
+    /// The lower plausible sensor voltage in millivolts
+    sensor_fault_low: Option<u16>,
+    /// The upper plausible sensor voltage in millivolts
+    sensor_fault_high: Option<u16>,
+    /// The active level of the sensor power-select pin
+    sensor_polarity: Option<SensorPolarity>,
+    /// The GPIO pin the sensor is connected to
+    sensor_pin: Option<SensorPin>,
+    /// Whether the chip temperature is sampled
+    sensor_temperature: Option<bool>,
+    /// Whether the sensor sample statistics are published
+    sensor_stats: Option<bool>,
+    /// The unit for published temperatures
+    sensor_temp_unit: Option<TemperatureUnit>,
+    /// The amount of leading ADC samples to discard
+    sensor_warmup: Option<u16>,
+    /// The target sampling duration
+    sensor_sample_ms: Option<Duration>,
+    /// The amount of wakes to average the sensor voltage over
+    sensor_average: Option<u16>,
+    /// The ADC reference voltage in millivolts
+    sensor_vref_mv: Option<u16>,
+    /// The amount of decimals for computed sensor values
+    sensor_decimals: Option<u16>,
+    /// The battery cutoff voltage in millivolts
+    battery_cutoff_mv: Option<u16>,
+    /// The status LED toggle interval
+    led_blink_ms: Option<Duration>,
+    /// The bench-mode sleep duration
+    bench_sleep_secs: Option<Duration>,
+    /// The soft awake-time budget
+    awake_budget_secs: Option<Duration>,
+    /// The maximum time in maintenance mode
+    maintenance_secs: Option<Duration>,
+    /// Whether to run in dry-run mode
+    dry_run: Option<bool>,
+    /// The first malformed key, if any
+    malformed: Option<&'static str>,
+}
+impl ConfigSlots<'static> {
+    /// Reads the config slots from the flash memory
+    fn read() -> Self {
+        Self::parse(&USERDATA)
+    }
+
+    /// Fills all empty slots with their defaults and logs a warning for each default value used
+    fn into_config(self) -> AppConfig {
+        /// Default duration
+        const DEFAULT_DURATION: Duration = Duration::from_secs(30);
+
+        AppConfig {
+            WIFI_SSID: Self::unwrap_or_default("WIFI_SSID", self.wifi_ssid, "DEFAULT_WIFI_SSID"),
+            WIFI_PASS: Self::unwrap_or_default("WIFI_PASS", self.wifi_pass, ""),
+            WIFI_AUTH: Self::unwrap_or_default("WIFI_AUTH", self.wifi_auth, WifiAuth::Auto),
+            MQTT_ADDR: Self::unwrap_or_default("MQTT_ADDR", self.mqtt_addr, "DEFAULT_MQTT_ADDR"),
+            MQTT_ADDR2: Self::unwrap_or_default("MQTT_ADDR2", self.mqtt_addr2, ""),
+            MQTT_USER: Self::unwrap_or_default("MQTT_USER", self.mqtt_user, ""),
+            MQTT_PASS: Self::unwrap_or_default("MQTT_PASS", self.mqtt_pass, ""),
+            MQTT_PRFX: Self::unwrap_or_default("MQTT_PRFX", self.mqtt_prfx, ""),
+            MQTT_TOPIC_FMT: Self::unwrap_or_default("MQTT_TOPIC_FMT", self.mqtt_topic_fmt, "{prefix}/{name}"),
+            MQTT_KEEPALIVE: Self::unwrap_or_default("MQTT_KEEPALIVE", self.mqtt_keepalive, APP_TIMEOUT),
+            MQTT_AVAIL: Self::unwrap_or_default("MQTT_AVAIL", self.mqtt_avail, ""),
+            MQTT_WS: Self::unwrap_or_default("MQTT_WS", self.mqtt_ws, ""),
+            MQTT_FORMAT: Self::unwrap_or_default("MQTT_FORMAT", self.mqtt_format, MqttFormat::Text),
+            MQTT_QOS: Self::unwrap_or_default("MQTT_QOS", self.mqtt_qos, MqttQos::AtMostOnce),
+            MQTT_RECONNECT: Self::unwrap_or_default("MQTT_RECONNECT", self.mqtt_reconnect, true),
+            MQTT_CLEAN: Self::unwrap_or_default("MQTT_CLEAN", self.mqtt_clean, true),
+            MQTT_HEARTBEAT_EVERY: Self::unwrap_or_default("MQTT_HEARTBEAT_EVERY", self.mqtt_heartbeat_every, 0),
+            NTP_SERVER: Self::unwrap_or_default("NTP_SERVER", self.ntp_server, ""),
+            SENSOR_SLEEP_SECS: Self::unwrap_or_default("SENSOR_SLEEP_SECS", self.sensor_sleep_secs, DEFAULT_DURATION),
+            SENSOR_DELTA: Self::unwrap_or_default("SENSOR_DELTA", self.sensor_delta, 0),
+            SENSOR_LOW: Self::unwrap_or_default("SENSOR_LOW", self.sensor_low, 0),
+            SENSOR_HIGH: Self::unwrap_or_default("SENSOR_HIGH", self.sensor_high, 0),
+            SENSOR_FAULT_LOW: Self::unwrap_or_default("SENSOR_FAULT_LOW", self.sensor_fault_low, FAULT_LOW_MILLIVOLTS),
+            SENSOR_FAULT_HIGH: Self::unwrap_or_default(
+                "SENSOR_FAULT_HIGH",
+                self.sensor_fault_high,
+                FAULT_HIGH_MILLIVOLTS,
+            ),
+            SENSOR_POLARITY: Self::unwrap_or_default(
+                "SENSOR_POLARITY",
+                self.sensor_polarity,
+                SensorPolarity::ActiveHigh,
+            ),
+            SENSOR_PIN: Self::unwrap_or_default("SENSOR_PIN", self.sensor_pin, SensorPin::Gpio28),
+            SENSOR_TEMPERATURE: Self::unwrap_or_default("SENSOR_TEMPERATURE", self.sensor_temperature, true),
+            SENSOR_STATS: Self::unwrap_or_default("SENSOR_STATS", self.sensor_stats, false),
+            SENSOR_TEMP_UNIT: Self::unwrap_or_default(
+                "SENSOR_TEMP_UNIT",
+                self.sensor_temp_unit,
+                TemperatureUnit::Celsius,
+            ),
+            SENSOR_WARMUP: Self::unwrap_or_default("SENSOR_WARMUP", self.sensor_warmup, WARMUP_SAMPLES),
+            SENSOR_SAMPLE_MS: Self::unwrap_or_default("SENSOR_SAMPLE_MS", self.sensor_sample_ms, Duration::MIN),
+            SENSOR_AVERAGE: Self::unwrap_or_default("SENSOR_AVERAGE", self.sensor_average, 1),
+            SENSOR_VREF_MV: Self::unwrap_or_default("SENSOR_VREF_MV", self.sensor_vref_mv, VREF_MILLIVOLTS),
+            SENSOR_DECIMALS: Self::unwrap_or_default("SENSOR_DECIMALS", self.sensor_decimals, 3),
+            BATTERY_CUTOFF_MV: Self::unwrap_or_default("BATTERY_CUTOFF_MV", self.battery_cutoff_mv, 0),
+            LED_BLINK_MS: Self::unwrap_or_default("LED_BLINK_MS", self.led_blink_ms, StatusLed::DEFAULT_INTERVAL),
+            BENCH_SLEEP_SECS: Self::unwrap_or_default("BENCH_SLEEP_SECS", self.bench_sleep_secs, Duration::MIN),
+            AWAKE_BUDGET_SECS: Self::unwrap_or_default("AWAKE_BUDGET_SECS", self.awake_budget_secs, Duration::MIN),
+            MAINTENANCE_SECS: Self::unwrap_or_default(
+                "MAINTENANCE_SECS",
+                self.maintenance_secs,
+                Duration::from_secs(600),
+            ),
+            DRY_RUN: Self::unwrap_or_default("DRY_RUN", self.dry_run, false),
+        }
+    }
+
+    /// Unwraps the given value or logs a warning and falls back to the default
+    fn unwrap_or_default<T>(name: &str, value: Option<T>, default: T) -> T {
+        if let Some(value) = value {
+            // Value has been set
+            value
+        } else {
+            // Log error and use default
+            debug_println!("[warn] using default config value: {}", name);
+            default
+        }
+    }
+}
+impl<'a> ConfigSlots<'a> {
+    /// Parses the config slots from the given INI-like data
+    fn parse(data: &'a [u8]) -> Self {
+        // Read config
+        let mut this = Self::default();
+        for (key, value) in Self::pairs(data) {
+            // Parse the value
+            let value = Self::unquote(value);
+            match key {
+                // Select correct slot
+                "WIFI_SSID" => Self::read_str(value, &mut this.wifi_ssid),
+                "WIFI_PASS" => Self::read_str(value, &mut this.wifi_pass),
+                "WIFI_AUTH" => {
+                    let valid = Self::read_enum(value, &mut this.wifi_auth, WifiAuth::parse);
+                    this.mark_malformed("WIFI_AUTH", valid);
+                }
+                "MQTT_ADDR" => Self::read_str(value, &mut this.mqtt_addr),
+                "MQTT_ADDR2" => Self::read_str(value, &mut this.mqtt_addr2),
+                "MQTT_USER" => Self::read_str(value, &mut this.mqtt_user),
+                "MQTT_PASS" => Self::read_str(value, &mut this.mqtt_pass),
+                "MQTT_PRFX" => Self::read_str(value, &mut this.mqtt_prfx),
+                "MQTT_TOPIC_FMT" => {
+                    let valid = Self::read_topic_fmt(value, &mut this.mqtt_topic_fmt);
+                    this.mark_malformed("MQTT_TOPIC_FMT", valid);
+                }
+                "MQTT_AVAIL" => Self::read_str(value, &mut this.mqtt_avail),
+                "MQTT_WS" => Self::read_str(value, &mut this.mqtt_ws),
+                "MQTT_FORMAT" => {
+                    let valid = Self::read_enum(value, &mut this.mqtt_format, MqttFormat::parse);
+                    this.mark_malformed("MQTT_FORMAT", valid);
+                }
+                "MQTT_QOS" => {
+                    let valid = Self::read_enum(value, &mut this.mqtt_qos, MqttQos::parse);
+                    this.mark_malformed("MQTT_QOS", valid);
+                }
+                "MQTT_RECONNECT" => {
+                    let valid = Self::read_bool(value, &mut this.mqtt_reconnect);
+                    this.mark_malformed("MQTT_RECONNECT", valid);
+                }
+                "MQTT_CLEAN" => {
+                    let valid = Self::read_bool(value, &mut this.mqtt_clean);
+                    this.mark_malformed("MQTT_CLEAN", valid);
+                }
+                "MQTT_HEARTBEAT_EVERY" => {
+                    let valid = Self::read_u16(value, &mut this.mqtt_heartbeat_every);
+                    this.mark_malformed("MQTT_HEARTBEAT_EVERY", valid);
+                }
+                "MQTT_KEEPALIVE" => {
+                    // Note: The keep-alive interval is transmitted as 16 bit seconds value
+                    let valid = Self::read_secs(value, &mut this.mqtt_keepalive);
+                    let fits = this.mqtt_keepalive.is_none_or(|keepalive| keepalive.as_secs() <= u16::MAX as u64);
+                    this.mqtt_keepalive = this.mqtt_keepalive.filter(|_| fits);
+                    this.mark_malformed("MQTT_KEEPALIVE", valid && fits);
+                }
+                "NTP_SERVER" => Self::read_str(value, &mut this.ntp_server),
+                "SENSOR_SLEEP_SECS" => {
+                    let valid = Self::read_secs(value, &mut this.sensor_sleep_secs);
+                    this.mark_malformed("SENSOR_SLEEP_SECS", valid);
+                }
+                "SENSOR_DELTA" => {
+                    let valid = Self::read_u16(value, &mut this.sensor_delta);
+                    this.mark_malformed("SENSOR_DELTA", valid);
+                }
+                "SENSOR_LOW" => {
+                    let valid = Self::read_u16(value, &mut this.sensor_low);
+                    this.mark_malformed("SENSOR_LOW", valid);
+                }
+                "SENSOR_HIGH" => {
+                    let valid = Self::read_u16(value, &mut this.sensor_high);
+                    this.mark_malformed("SENSOR_HIGH", valid);
+                }
+                "SENSOR_FAULT_LOW" => {
+                    let valid = Self::read_u16(value, &mut this.sensor_fault_low);
+                    this.mark_malformed("SENSOR_FAULT_LOW", valid);
+                }
+                "SENSOR_FAULT_HIGH" => {
+                    let valid = Self::read_u16(value, &mut this.sensor_fault_high);
+                    this.mark_malformed("SENSOR_FAULT_HIGH", valid);
+                }
+                "SENSOR_POLARITY" => {
+                    let valid = Self::read_enum(value, &mut this.sensor_polarity, SensorPolarity::parse);
+                    this.mark_malformed("SENSOR_POLARITY", valid);
+                }
+                "SENSOR_PIN" => {
+                    let valid = Self::read_enum(value, &mut this.sensor_pin, SensorPin::parse);
+                    this.mark_malformed("SENSOR_PIN", valid);
+                }
+                "SENSOR_TEMPERATURE" => {
+                    let valid = Self::read_bool(value, &mut this.sensor_temperature);
+                    this.mark_malformed("SENSOR_TEMPERATURE", valid);
+                }
+                "SENSOR_STATS" => {
+                    let valid = Self::read_bool(value, &mut this.sensor_stats);
+                    this.mark_malformed("SENSOR_STATS", valid);
+                }
+                "SENSOR_TEMP_UNIT" => {
+                    let valid = Self::read_enum(value, &mut this.sensor_temp_unit, TemperatureUnit::parse);
+                    this.mark_malformed("SENSOR_TEMP_UNIT", valid);
+                }
+                "SENSOR_WARMUP" => {
+                    // Note: At least one sample must be left to compute the mean
+                    let valid = Self::read_u16(value, &mut this.sensor_warmup);
+                    let fits = this.sensor_warmup.is_none_or(|warmup| (warmup as usize) < SAMPLE_COUNT);
+                    this.sensor_warmup = this.sensor_warmup.filter(|_| fits);
+                    this.mark_malformed("SENSOR_WARMUP", valid && fits);
+                }
+                "SENSOR_SAMPLE_MS" => {
+                    let valid = Self::read_millis(value, &mut this.sensor_sample_ms);
+                    this.mark_malformed("SENSOR_SAMPLE_MS", valid);
+                }
+                "SENSOR_AVERAGE" => {
+                    // Note: The last reading of an average is never stored, so the limit is one above the stored count
+                    let valid = Self::read_u16(value, &mut this.sensor_average);
+                    let limit = 1..=ReadingAverage::COUNT_MAX + 1;
+                    let fits = this.sensor_average.is_none_or(|average| limit.contains(&average));
+                    this.sensor_average = this.sensor_average.filter(|_| fits);
+                    this.mark_malformed("SENSOR_AVERAGE", valid && fits);
+                }
+                "SENSOR_VREF_MV" => {
+                    // Note: A zero reference voltage would turn every reading into zero
+                    let valid = Self::read_u16(value, &mut this.sensor_vref_mv);
+                    let nonzero = this.sensor_vref_mv.is_none_or(|vref_mv| vref_mv > 0);
+                    this.sensor_vref_mv = this.sensor_vref_mv.filter(|_| nonzero);
+                    this.mark_malformed("SENSOR_VREF_MV", valid && nonzero);
+                }
+                "SENSOR_DECIMALS" => {
+                    // Note: More decimals than this are pure noise for a 12 bit ADC, and may overflow the value buffer
+                    let valid = Self::read_u16(value, &mut this.sensor_decimals);
+                    let fits = this.sensor_decimals.is_none_or(|decimals| decimals <= DECIMALS_MAX);
+                    this.sensor_decimals = this.sensor_decimals.filter(|_| fits);
+                    this.mark_malformed("SENSOR_DECIMALS", valid && fits);
+                }
+                "BATTERY_CUTOFF_MV" => {
+                    let valid = Self::read_u16(value, &mut this.battery_cutoff_mv);
+                    this.mark_malformed("BATTERY_CUTOFF_MV", valid);
+                }
+                "LED_BLINK_MS" => {
+                    // Note: A zero interval would make the status LED task spin
+                    let valid = Self::read_millis(value, &mut this.led_blink_ms);
+                    let nonzero = this.led_blink_ms.is_none_or(|interval| interval > Duration::MIN);
+                    this.led_blink_ms = this.led_blink_ms.filter(|_| nonzero);
+                    this.mark_malformed("LED_BLINK_MS", valid && nonzero);
+                }
+                "BENCH_SLEEP_SECS" => {
+                    let valid = Self::read_secs(value, &mut this.bench_sleep_secs);
+                    this.mark_malformed("BENCH_SLEEP_SECS", valid);
+                }
+                "AWAKE_BUDGET_SECS" => {
+                    let valid = Self::read_secs(value, &mut this.awake_budget_secs);
+                    this.mark_malformed("AWAKE_BUDGET_SECS", valid);
+                }
+                "MAINTENANCE_SECS" => {
+                    let valid = Self::read_secs(value, &mut this.maintenance_secs);
+                    this.mark_malformed("MAINTENANCE_SECS", valid);
+                }
+                "DRY_RUN" => {
+                    let valid = Self::read_bool(value, &mut this.dry_run);
+                    this.mark_malformed("DRY_RUN", valid);
+                }
+                // Unknown INI line; skip it
+                _ => continue,
+            };
+        }
+        this
+    }
+
+    /// Iterates over the trimmed keys and the raw values of the INI key-value pairs in the given INI-like data
+    ///
+    /// # Note
+    /// Comment lines and lines without `=` are skipped, and the iteration stops at the first line that is not UTF-8, as
+    /// we are not in the INI section anymore.
+    fn pairs(data: &'a [u8]) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let lines = data.split(|byte| *byte == b'\n').map_while(|line| str::from_utf8(line).ok());
+        let lines = lines.filter(|line| !line.trim_start().starts_with(['#', ';']));
+        lines.filter_map(|line| line.split_once('=')).map(|(key, value)| (key.trim(), value))
+    }
+
+    /// Records the given key as malformed if the value was invalid and no other key has been recorded yet
+    fn mark_malformed(&mut self, name: &'static str, valid: bool) {
+        if !valid && self.malformed.is_none() {
+            self.malformed = Some(name);
+        }
+    }
+
+    /// Trims the given value, and strips the surrounding double quotes if any
+    ///
+    /// # Note
+    /// Quoted values are preserved exactly, including leading/trailing whitespace and special characters.
+    fn unquote(value: &'a str) -> &'a str {
+        let value = value.trim();
+        value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+    }
+
+    /// Reads a string value into the given target slot if the slot is empty
+    fn read_str(value: &'a str, target: &mut Option<&'a str>) {
+        if target.is_none() {
+            // Set value
+            *target = Some(value);
+        }
+    }
+
+    /// Parses a value with the given parser into the given target slot if the slot is empty, and returns whether the
+    /// value is valid
+    fn read_enum<T>(value: &str, target: &mut Option<T>, parse: fn(&str) -> Option<T>) -> bool {
+        if target.is_none() {
+            // Parse the value
+            let Some(parsed) = parse(value) else {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            };
+
+            // Set value
+            *target = Some(parsed);
+        }
+        true
+    }
+
+    /// Reads an MQTT topic template into the given target slot if the slot is empty, and returns whether the value is
+    /// valid
+    ///
+    /// # Note
+    /// A valid template contains the `{name}` placeholder, only known placeholders, and no wildcards or null characters.
+    fn read_topic_fmt(value: &'a str, target: &mut Option<&'a str>) -> bool {
+        if target.is_none() {
+            // Validate the placeholders
+            let (mut rest, mut has_name) = (value, false);
+            while let Some((_, placeholder)) = rest.split_once('{') {
+                let Some((placeholder, tail)) = placeholder.split_once('}') else {
+                    // Log warning and ignore
+                    debug_println!("[warn] invalid config value: {}", value);
+                    return false;
+                };
+                match placeholder {
+                    "name" => has_name = true,
+                    "prefix" | "device" => (),
+                    _ => {
+                        // Log warning and ignore
+                        debug_println!("[warn] invalid config value: {}", value);
+                        return false;
+                    }
+                }
+                rest = tail;
+            }
+
+            // Reject templates without name or with illegal characters
+            if !has_name || value.contains(['+', '#', '\0']) {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            }
+
+            // Set value
+            *target = Some(value);
+        }
+        true
+    }
+
+    /// Reads a boolean (`true` or `false`) into the given target slot if the slot is empty, and returns whether the value
+    /// is valid
+    fn read_bool(value: &str, target: &mut Option<bool>) -> bool {
+        if target.is_none() {
+            // Parse the value
+            let Ok(flag) = value.parse() else {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            };
+
+            // Set value
+            *target = Some(flag);
+        }
+        true
+    }
+
+    /// Reads an integer into the given target slot if the slot is empty, and returns whether the value is valid
+    fn read_u16(value: &str, target: &mut Option<u16>) -> bool {
+        if target.is_none() {
+            // Parse the value
+            let Ok(number) = value.parse() else {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            };
+
+            // Set value
+            *target = Some(number);
+        }
+        true
+    }
+
+    /// Reads a duration in milliseconds into the given target slot if the slot is empty, and returns whether the value is
+    /// valid
+    fn read_millis(value: &str, target: &mut Option<Duration>) -> bool {
+        if target.is_none() {
+            // Parse the value
+            let Ok(millis) = value.parse() else {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            };
+
+            // Set value
+            *target = Some(Duration::from_millis(millis));
+        }
+        true
+    }
+
+    /// Reads a duration into the given target slot if the slot is empty, and returns whether the value is valid
+    ///
+    /// # Note
+    /// The duration may have an `s`, `m` or `h` suffix; unsuffixed numbers are seconds.
+    fn read_secs(value: &str, target: &mut Option<Duration>) -> bool {
+        if target.is_none() {
+            // Split number and unit
+            let (number, unit_secs) = [('s', 1), ('m', 60), ('h', 60 * 60)]
+                .into_iter()
+                .find_map(|(suffix, unit_secs)| Some((value.strip_suffix(suffix)?, unit_secs)))
+                .unwrap_or((value, 1));
+
+            // Parse the value
+            let Some(secs) = number.parse::<u64>().ok().and_then(|number| number.checked_mul(unit_secs)) else {
+                // Log warning and ignore
+                debug_println!("[warn] invalid config value: {}", value);
+                return false;
+            };
+
+            // Set value
+            let value = Duration::from_secs(secs);
+            *target = Some(value);
+        }
+        true
+    }
+}
@@ -0,0 +1,358 @@
+//! User communication for status update
+
+use crate::wifi::Cyw43Session;
+#[cfg(not(feature = "uart-log"))]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_executor::Spawner;
+#[cfg(feature = "uart-log")]
+use embassy_rp::Peri;
+#[cfg(feature = "uart-log")]
+use embassy_rp::uart::{Async, Config, Instance, TxPin, UartTx};
+#[cfg(feature = "uart-log")]
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+#[cfg(feature = "uart-log")]
+use embassy_sync::pipe::Pipe;
+use embassy_time::{Duration, Ticker, Timer};
+
+// Re-export the semihosting print macro for `debug_println!`, so the board crates don't need to depend on it
+#[doc(hidden)]
+pub use cortex_m_semihosting::hprintln;
+
+/// Prints a line via semi-hosting for debug builds only, or via UART for all builds if the `uart-log` feature is enabled
+#[macro_export]
+macro_rules! debug_println {
+    ($s:expr) => {{
+        #[cfg(feature = "uart-log")]
+        {
+            // Queue for UART output
+            $crate::debug::uart_log(format_args!($s));
+        }
+        #[cfg(not(feature = "uart-log"))]
+        if cfg!(debug_assertions) && $crate::debug::semihosting_available() {
+            // Print via semihosting
+            // Note: Without an attached debugger, the first print fails and disables further prints
+            $crate::debug::hprintln!($s);
+        }
+    }};
+    ($s:expr, $($tt:tt)*) => {{
+        #[cfg(feature = "uart-log")]
+        {
+            // Queue for UART output
+            $crate::debug::uart_log(format_args!($s, $($tt)*));
+        }
+        #[cfg(not(feature = "uart-log"))]
+        if cfg!(debug_assertions) && $crate::debug::semihosting_available() {
+            // Print via semihosting
+            // Note: Without an attached debugger, the first print fails and disables further prints
+            $crate::debug::hprintln!($s, $($tt)*);
+        }
+    }};
+}
+
+/// Whether semihosting calls reach a debugger
+///
+/// # Note
+/// This is cleared by the hard-fault handler below once a semihosting call faults because no debugger is attached.
+#[cfg(not(feature = "uart-log"))]
+static SEMIHOSTING: AtomicBool = AtomicBool::new(true);
+
+/// Whether semihosting calls reach a debugger, see [`debug_println!`](crate::debug_println)
+#[doc(hidden)]
+#[cfg(not(feature = "uart-log"))]
+pub fn semihosting_available() -> bool {
+    SEMIHOSTING.load(Ordering::SeqCst)
+}
+
+// Hard-fault handler that turns semihosting calls without a debugger into no-ops
+//
+// Note: Without an attached debugger, the semihosting breakpoint (`bkpt 0xab`, encoded as `0xbeab`) escalates to a hard
+//  fault. This handler overrides the default handler of `cortex-m-rt`; if the faulting instruction is such a
+//  breakpoint, it is skipped with `-1` as result, so the semihosting call fails gracefully, and further prints are
+//  disabled. Any other fault spins like the default handler until the watchdog resets the device. The code is ARMv6-M
+//  compatible, so it can be shared by the RP2040 and the RP2350.
+#[cfg(not(feature = "uart-log"))]
+core::arch::global_asm!(
+    ".section .text.HardFault, \"ax\"",
+    ".global HardFault",
+    ".type HardFault, %function",
+    ".thumb_func",
+    "HardFault:",
+    // Select the stack of the exception frame via bit 2 of EXC_RETURN
+    "    movs r0, #4",
+    "    mov r1, lr",
+    "    tst r0, r1",
+    "    beq 1f",
+    "    mrs r0, psp",
+    "    b 2f",
+    "1:  mrs r0, msp",
+    // Check whether the stacked PC points to a semihosting breakpoint
+    "2:  ldr r1, [r0, #24]",
+    "    ldrh r2, [r1]",
+    "    ldr r3, =0xbeab",
+    "    cmp r2, r3",
+    "    bne 3f",
+    // Skip the breakpoint, and return -1 in the stacked r0
+    "    adds r1, #2",
+    "    str r1, [r0, #24]",
+    "    movs r2, #0",
+    "    mvns r2, r2",
+    "    str r2, [r0]",
+    // Disable further semihosting calls
+    "    ldr r3, ={semihosting}",
+    "    movs r2, #0",
+    "    strb r2, [r3]",
+    "    bx lr",
+    "3:  b 3b",
+    ".ltorg",
+    semihosting = sym SEMIHOSTING,
+);
+
+/// The UART log queue
+#[cfg(feature = "uart-log")]
+static UART_LOG: Pipe<CriticalSectionRawMutex, 1024> = Pipe::new();
+
+/// Queues a line for UART output
+///
+/// # Note
+/// This is best-effort and never blocks; if the queue is full because the host is not reading, the line is truncated or
+/// dropped.
+#[cfg(feature = "uart-log")]
+pub fn uart_log(args: core::fmt::Arguments) {
+    /// A writer into the UART log queue
+    struct UartLog;
+    impl core::fmt::Write for UartLog {
+        fn write_str(&mut self, str_: &str) -> core::fmt::Result {
+            let _ = UART_LOG.try_write(str_.as_bytes());
+            Ok(())
+        }
+    }
+
+    // Format line
+    use core::fmt::Write;
+    let _ = writeln!(UartLog, "{args}");
+}
+
+/// Starts the UART log task on the given UART TX pin (115200 baud, 8N1)
+#[cfg(feature = "uart-log")]
+pub fn start_uart_log<U, P, D>(uart: Peri<'static, U>, tx: Peri<'static, P>, dma: Peri<'static, D>, spawner: &Spawner)
+where
+    U: Instance,
+    P: TxPin<U>,
+    D: embassy_rp::dma::Channel,
+{
+    let uart = UartTx::new(uart, tx, dma, Config::default());
+    spawner.must_spawn(uart_log_task(uart));
+}
+
+/// UART log task
+#[cfg(feature = "uart-log")]
+#[embassy_executor::task]
+async fn uart_log_task(mut uart: UartTx<'static, Async>) {
+    let mut buf = [0; 64];
+    loop {
+        // Forward queued log data
+        let len = UART_LOG.read(&mut buf).await;
+        let _ = uart.write(&buf[..len]).await;
+    }
+}
+
+/// Status LED mode
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum StatusLedMode {
+    /// LED off
+    Off,
+    /// LED on
+    On,
+    /// Fast blinking (4/s at the default interval)
+    Blink,
+    /// Slow blinking (1/s at the default interval)
+    BlinkSlow,
+    /// Morse SOS (three short, three long and three short blinks every ~4s at the default interval)
+    Sos,
+    /// Triple blinking (three short blinks every 2s at the default interval)
+    BlinkTriple,
+    /// Double blinking (two short blinks every 2s at the default interval)
+    BlinkDouble,
+    /// Breathing (a soft brightness ramp every 2s at the default interval)
+    Breathe,
+    /// Counted short blinks followed by a pause, see [`StatusLedSession::identify`]
+    Identify,
+}
+
+/// The status LED handler
+pub struct StatusLed {
+    /// Status LED mode
+    mode: AtomicU8,
+    /// The amount of blinks for the identification mode
+    flashes: AtomicU8,
+    /// Radio peripheral (the LED is controlled via the radio lol)
+    radio: &'static Cyw43Session,
+    /// The toggle interval for LED blinking
+    interval: Duration,
+}
+impl StatusLed {
+    /// The default toggle interval for LED blinking
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(125);
+
+    /// Creates a new status LED handler with the given toggle interval
+    pub const fn new(radio: &'static Cyw43Session, interval: Duration) -> Self {
+        let (mode, flashes) = (AtomicU8::new(StatusLedMode::Off as u8), AtomicU8::new(0));
+        Self { mode, flashes, radio, interval }
+    }
+
+    /// Starts the status LED task
+    pub fn start(&'static self, spawner: &Spawner) -> StatusLedSession {
+        spawner.must_spawn(status_led_task(&self.mode, &self.flashes, self.radio, self.interval));
+        StatusLedSession { mode: &self.mode, flashes: &self.flashes, interval: self.interval }
+    }
+}
+
+/// A [`StatusLed`] session
+#[derive(Clone, Copy)]
+pub struct StatusLedSession {
+    /// LED mode
+    mode: &'static AtomicU8,
+    /// The amount of blinks for the identification mode
+    flashes: &'static AtomicU8,
+    /// The toggle interval for LED blinking
+    interval: Duration,
+}
+impl StatusLedSession {
+    /// Sets the status LED to the given mode
+    pub fn set(&self, mode: StatusLedMode) {
+        self.mode.store(mode as u8, Ordering::SeqCst);
+    }
+
+    /// Blinks the status LED the given amount of times followed by a pause, and returns once the pattern has been shown
+    ///
+    /// # Note
+    /// The LED is left in the identification mode afterwards, so the caller should set the next mode immediately.
+    pub async fn identify(&self, flashes: u8) {
+        self.flashes.store(flashes, Ordering::SeqCst);
+        self.set(StatusLedMode::Identify);
+        Timer::after(self.interval * identify_len(flashes)).await;
+    }
+}
+
+/// [`StatusLed`] task
+#[embassy_executor::task]
+async fn status_led_task(
+    mode: &'static AtomicU8,
+    flashes: &'static AtomicU8,
+    radio: &'static Cyw43Session,
+    interval: Duration,
+) {
+    // Init the LED to a known state
+    let mut ticker = Ticker::every(interval);
+    let mut state = false;
+    let mut ticks: u32 = 0;
+    let (mut last_mode, mut mode_since) = (mode.load(Ordering::SeqCst), 0);
+    radio.set_led(state).await;
+
+    // Toggle state if appropriate
+    while !radio.is_shutdown() {
+        // Track mode changes, so the identification pattern always starts with its first blink
+        if mode.load(Ordering::SeqCst) != last_mode {
+            (last_mode, mode_since) = (mode.load(Ordering::SeqCst), ticks);
+        }
+
+        // Render the breathing mode as software PWM over the entire interval
+        if mode.load(Ordering::SeqCst) == StatusLedMode::Breathe as u8 {
+            state = breathe(radio, ticks, interval).await;
+            ticks = ticks.wrapping_add(1);
+            ticker.next().await;
+            continue;
+        }
+
+        // Determine whether we should toggle the LED state
+        let wants_toggle = match mode.load(Ordering::SeqCst) {
+            mode if mode == StatusLedMode::Off as u8 => state != false,
+            mode if mode == StatusLedMode::On as u8 => state != true,
+            mode if mode == StatusLedMode::Blink as u8 => true,
+            mode if mode == StatusLedMode::BlinkSlow as u8 => ticks.is_multiple_of(4),
+            mode if mode == StatusLedMode::Sos as u8 => state != sos(ticks),
+            mode if mode == StatusLedMode::BlinkTriple as u8 => state != matches!(ticks % 16, 0 | 2 | 4),
+            mode if mode == StatusLedMode::BlinkDouble as u8 => state != matches!(ticks % 16, 0 | 2),
+            // Note: Breathing is rendered separately above, but the mode may have changed in between
+            mode if mode == StatusLedMode::Breathe as u8 => false,
+            mode if mode == StatusLedMode::Identify as u8 => {
+                state != identify(ticks.wrapping_sub(mode_since), flashes.load(Ordering::SeqCst))
+            }
+            mode => unreachable!("invalid status led mode: {mode}"),
+        };
+
+        // Update state if appropriate
+        if wants_toggle {
+            state = !state;
+            radio.set_led(state).await;
+        }
+
+        // Sleep some time
+        ticks = ticks.wrapping_add(1);
+        ticker.next().await;
+    }
+}
+
+/// Computes the LED state of the morse SOS pattern for the given tick
+///
+/// # Note
+/// A dot is one tick, a dash is three ticks, and elements, letters and repetitions are separated by one, three and seven
+/// ticks respectively.
+const fn sos(ticks: u32) -> bool {
+    /// The pattern, where each bit is the LED state for one tick (LSB first)
+    #[allow(clippy::unusual_byte_groupings, reason = "the digits are grouped by morse letters and gaps")]
+    const PATTERN: u64 = 0b0000000_10101_000_11101110111_000_10101;
+    /// The pattern length in ticks
+    const PATTERN_LEN: u32 = 34;
+
+    (PATTERN >> (ticks % PATTERN_LEN)) & 1 != 0
+}
+
+/// The pause after the identification blinks in ticks
+const IDENTIFY_PAUSE: u32 = 8;
+
+/// Computes the length of the identification pattern with the given amount of blinks in ticks
+const fn identify_len(flashes: u8) -> u32 {
+    2 * flashes as u32 + IDENTIFY_PAUSE
+}
+
+/// Computes the LED state of the identification pattern with the given amount of blinks for the given tick
+///
+/// # Note
+/// A blink is one tick on and one tick off, and repetitions are separated by a pause of [`IDENTIFY_PAUSE`] ticks.
+const fn identify(ticks: u32, flashes: u8) -> bool {
+    let phase = ticks % identify_len(flashes);
+    phase < 2 * flashes as u32 && phase.is_multiple_of(2)
+}
+
+/// Renders the breathing pattern for the given interval via software PWM, and returns the final LED state
+///
+/// # Note
+/// The onboard LED is connected to the radio chip and has no hardware PWM, so every edge is a radio bus transfer.
+async fn breathe(radio: &Cyw43Session, ticks: u32, interval: Duration) -> bool {
+    /// The software PWM period
+    const PWM_PERIOD: Duration = Duration::from_millis(25);
+    /// The amount of brightness levels per ramp
+    const LEVELS: u32 = 8;
+
+    // Compute the brightness level as triangle wave over two ramps
+    let phase = ticks % (2 * LEVELS);
+    let level = if phase < LEVELS { phase } else { 2 * LEVELS - phase };
+    let on = PWM_PERIOD * level / LEVELS;
+    let off = PWM_PERIOD - on;
+
+    // Render PWM periods
+    for _ in 0..(interval.as_ticks() / PWM_PERIOD.as_ticks()) {
+        if on > Duration::MIN {
+            radio.set_led(true).await;
+            Timer::after(on).await;
+        }
+        if off > Duration::MIN {
+            radio.set_led(false).await;
+            Timer::after(off).await;
+        }
+    }
+    off == Duration::MIN
+}
@@ -0,0 +1,24 @@
+//! Device identification
+
+use core::fmt::{Display, Formatter};
+
+/// The unique 64-bit device ID
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceId([u8; 8]);
+impl DeviceId {
+    /// Creates a device ID from the given unique chip or flash ID
+    pub const fn new(id: [u8; 8]) -> Self {
+        Self(id)
+    }
+
+    /// The last hex digit of the device ID as displayed
+    pub const fn last_digit(&self) -> u8 {
+        self.0[7] & 0x0F
+    }
+}
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        // Format as lowercase hex
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
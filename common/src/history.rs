@@ -0,0 +1,138 @@
+//! Flash-backed backlog of readings that could not be published
+
+use crate::config::FLASH_SIZE;
+use embassy_rp::Peri;
+use embassy_rp::flash::{Blocking, FLASH_BASE, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_time::Duration;
+
+/// The history sector size
+const HISTORY_SIZE: usize = 4096;
+/// The serialized entry size
+const ENTRY_SIZE: usize = 8;
+/// The maximum amount of entries within the history sector
+const ENTRY_COUNT: usize = HISTORY_SIZE / ENTRY_SIZE;
+/// The amount of newest entries that are kept if the history sector is full
+const ENTRY_KEEP: usize = ENTRY_COUNT / 2;
+/// The entry checksum XOR constant, so that erased flash is never a valid entry
+const CHECKSUM_XOR: u16 = 0x5A5A;
+
+/// History section in flash
+#[unsafe(link_section = ".history")]
+static HISTORY: [u8; HISTORY_SIZE] = [0; HISTORY_SIZE];
+
+/// A buffered reading
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryEntry {
+    /// The sensor voltage in millivolts
+    pub millivolts: u16,
+    /// The sleep duration after the reading in seconds
+    pub gap_secs: u32,
+}
+impl HistoryEntry {
+    /// Serializes the entry
+    fn encode(self) -> [u8; ENTRY_SIZE] {
+        let checksum = Self::checksum(self.millivolts, self.gap_secs);
+        let ([gap0, gap1, gap2, gap3], [mv0, mv1], [sum0, sum1]) =
+            (self.gap_secs.to_le_bytes(), self.millivolts.to_le_bytes(), checksum.to_le_bytes());
+        [gap0, gap1, gap2, gap3, mv0, mv1, sum0, sum1]
+    }
+
+    /// Deserializes an entry, or returns `None` if the entry is corrupt
+    fn decode(bytes: [u8; ENTRY_SIZE]) -> Option<Self> {
+        let gap_secs = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let millivolts = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let checksum = u16::from_le_bytes([bytes[6], bytes[7]]);
+        match checksum == Self::checksum(millivolts, gap_secs) {
+            true => Some(Self { millivolts, gap_secs }),
+            false => None,
+        }
+    }
+
+    /// Computes the entry checksum
+    const fn checksum(millivolts: u16, gap_secs: u32) -> u16 {
+        millivolts ^ gap_secs as u16 ^ (gap_secs >> 16) as u16 ^ CHECKSUM_XOR
+    }
+}
+
+/// The backlog of readings that could not be published, persisted in a dedicated flash sector
+///
+/// # Important
+/// The history must not be modified while the radio is active, as the flash is unavailable during erase and write.
+pub struct History<'d> {
+    /// The flash driver
+    flash: Flash<'d, FLASH, Blocking, FLASH_SIZE>,
+}
+impl<'d> History<'d> {
+    /// Creates a new history handle
+    pub fn new(flash: Peri<'d, FLASH>) -> Self {
+        Self { flash: Flash::new_blocking(flash) }
+    }
+
+    /// The amount of occupied entry slots
+    ///
+    /// # Note
+    /// Entries are appended sequentially, so the first erased slot marks the end of the history.
+    pub fn len(&mut self) -> usize {
+        (0..ENTRY_COUNT).find(|&index| self.read_slot(index) == [0xFF; ENTRY_SIZE]).unwrap_or(ENTRY_COUNT)
+    }
+
+    /// Whether the history is empty
+    pub fn is_empty(&mut self) -> bool {
+        self.read_slot(0) == [0xFF; ENTRY_SIZE]
+    }
+
+    /// Reads the entry at the given slot index, or returns `None` if the entry is corrupt
+    pub fn get(&mut self, index: usize) -> Option<HistoryEntry> {
+        HistoryEntry::decode(self.read_slot(index))
+    }
+
+    /// Appends a reading with the given sleep duration until the next reading, and drops the oldest half of the entries
+    /// if the history sector is full
+    pub fn push(&mut self, millivolts: u16, gap: Duration) {
+        // Keep only the newest entries if the history sector is full
+        // Note: Flash can only be erased sector-wise, so the kept entries are rewritten into the erased sector
+        let mut len = self.len();
+        if len == ENTRY_COUNT {
+            let mut kept = [0; ENTRY_KEEP * ENTRY_SIZE];
+            let offset = Self::offset() + ((ENTRY_COUNT - ENTRY_KEEP) * ENTRY_SIZE) as u32;
+            self.flash.blocking_read(offset, &mut kept).expect("failed to read history sector");
+            self.erase();
+            self.flash.blocking_write(Self::offset(), &kept).expect("failed to write history sector");
+            len = ENTRY_KEEP;
+        }
+
+        // Write the entry into the next erased slot
+        let gap_secs = u32::try_from(gap.as_secs()).unwrap_or(u32::MAX);
+        let entry = HistoryEntry { millivolts, gap_secs }.encode();
+        let offset = Self::offset() + (len * ENTRY_SIZE) as u32;
+        self.flash.blocking_write(offset, &entry).expect("failed to write history sector");
+    }
+
+    /// Clears the history if it is not empty
+    pub fn clear(&mut self) {
+        // Note: Erasing wears the flash, so an empty history is left untouched
+        if !self.is_empty() {
+            self.erase();
+        }
+    }
+
+    /// Reads the raw entry at the given slot index
+    fn read_slot(&mut self, index: usize) -> [u8; ENTRY_SIZE] {
+        let mut slot = [0; ENTRY_SIZE];
+        let offset = Self::offset() + (index * ENTRY_SIZE) as u32;
+        self.flash.blocking_read(offset, &mut slot).expect("failed to read history sector");
+        slot
+    }
+
+    /// Erases the history sector
+    fn erase(&mut self) {
+        let offset = Self::offset();
+        self.flash.blocking_erase(offset, offset + HISTORY_SIZE as u32).expect("failed to erase history sector");
+    }
+
+    /// The flash offset of the history sector
+    fn offset() -> u32 {
+        HISTORY.as_ptr() as u32 - FLASH_BASE as u32
+    }
+}
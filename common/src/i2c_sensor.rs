@@ -0,0 +1,113 @@
+//! External SHT3x air temperature and humidity sensor handling
+
+use crate::Irqs;
+use core::fmt::{Display, Formatter};
+use embassy_rp::Peri;
+use embassy_rp::i2c::{AbortReason, Async, Config, Error, I2c, Instance, InterruptHandler, SclPin, SdaPin};
+use embassy_rp::interrupt::typelevel::Binding;
+use embassy_time::{Duration, Timer};
+
+/// The default SHT3x I2C address (`ADDR` pin pulled low)
+const SHT3X_ADDRESS: u8 = 0x44;
+/// The single-shot measurement command with high repeatability and without clock stretching
+const SHT3X_MEASURE: [u8; 2] = [0x24, 0x00];
+/// The maximum measurement duration with high repeatability
+const SHT3X_MEASURE_DURATION: Duration = Duration::from_millis(16);
+
+/// An air sensor readout result
+pub struct AirReadout {
+    /// The air temperature in degrees Celsius
+    pub temperature: f64,
+    /// The relative humidity in percent
+    pub humidity: f64,
+}
+
+/// An air sensor read error
+#[derive(Debug)]
+pub enum AirSensorError {
+    /// No sensor responds on the bus
+    Missing,
+    /// The I2C transfer failed
+    I2c(Error),
+    /// The sensor response is corrupt
+    Checksum,
+}
+impl Display for AirSensorError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "no air sensor connected"),
+            Self::I2c(error) => write!(f, "failed to communicate with air sensor: {error:?}"),
+            Self::Checksum => write!(f, "invalid air sensor checksum"),
+        }
+    }
+}
+
+/// The external SHT3x air sensor
+pub struct AirSensor<'d, T>
+where
+    T: Instance + 'd,
+{
+    /// I2C driver
+    i2c: I2c<'d, T, Async>,
+}
+impl<'d, T> AirSensor<'d, T>
+where
+    T: Instance + 'd,
+{
+    /// Creates a new air sensor instance on the given I2C pins
+    pub fn new<C, D>(i2c: Peri<'d, T>, irqs: Irqs, scl: Peri<'d, C>, sda: Peri<'d, D>) -> Self
+    where
+        Irqs: Binding<T::Interrupt, InterruptHandler<T>>,
+        C: SclPin<T>,
+        D: SdaPin<T>,
+    {
+        let i2c = I2c::new_async(i2c, scl, sda, irqs, Config::default());
+        Self { i2c }
+    }
+
+    /// Reads the air sensor
+    ///
+    /// # Note
+    /// If no sensor is connected, the address is not acknowledged and this function fails with
+    /// [`AirSensorError::Missing`].
+    pub async fn read(&mut self) -> Result<AirReadout, AirSensorError> {
+        // Trigger a single-shot measurement and give the sensor time to complete it
+        self.i2c.write_async(SHT3X_ADDRESS, SHT3X_MEASURE).await.map_err(Self::map_error)?;
+        Timer::after(SHT3X_MEASURE_DURATION).await;
+
+        // Read the temperature and humidity words with their checksums
+        let mut response = [0; 6];
+        self.i2c.read_async(SHT3X_ADDRESS, &mut response).await.map_err(Self::map_error)?;
+        let [temperature, humidity] = [&response[0..3], &response[3..6]];
+        if crc8(&temperature[..2]) != temperature[2] || crc8(&humidity[..2]) != humidity[2] {
+            // Reject corrupt responses
+            return Err(AirSensorError::Checksum);
+        }
+
+        // Convert the raw values
+        // Note: According to chapter 4.13 Conversion of Signal Output in the SHT3x datasheet
+        let temperature = u16::from_be_bytes([temperature[0], temperature[1]]) as f64;
+        let humidity = u16::from_be_bytes([humidity[0], humidity[1]]) as f64;
+        Ok(AirReadout { temperature: -45.0 + 175.0 * temperature / 65535.0, humidity: 100.0 * humidity / 65535.0 })
+    }
+
+    /// Maps an I2C error, so that an unacknowledged address signals a missing sensor
+    fn map_error(error: Error) -> AirSensorError {
+        match error {
+            Error::Abort(AbortReason::NoAcknowledge) => AirSensorError::Missing,
+            error => AirSensorError::I2c(error),
+        }
+    }
+}
+
+/// Computes the SHT3x CRC-8 checksum (polynomial `0x31`, initial value `0xFF`)
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0xFF;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,72 @@
+//! Board-independent firmware logic shared by the board-specific firmware crates
+//!
+//! # Note
+//! Exactly one of the chip features (`rp2040` or `rp235xa`) must be enabled, as it selects the chip for `embassy-rp`.
+//! Without any chip feature, only the chip-independent modules are built, so `cargo test` can run them on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(all(feature = "firmware", not(any(feature = "rp2040", feature = "rp235xa"))))]
+compile_error!("either the `rp2040` or the `rp235xa` feature must be enabled");
+
+#[cfg(feature = "firmware")]
+pub mod app;
+#[cfg(feature = "firmware")]
+pub mod budget;
+#[cfg(feature = "firmware")]
+pub mod config;
+#[cfg(feature = "firmware")]
+pub mod debug;
+pub mod device;
+#[cfg(feature = "firmware")]
+pub mod history;
+#[cfg(feature = "firmware")]
+pub mod i2c_sensor;
+#[cfg(feature = "firmware")]
+pub mod lifecycle;
+#[cfg(feature = "firmware")]
+pub mod mqtt;
+pub mod mqtt_codec;
+#[cfg(feature = "firmware")]
+pub mod ntp;
+#[cfg(feature = "firmware")]
+pub mod onewire;
+#[cfg(feature = "firmware")]
+pub mod ota;
+#[cfg(feature = "firmware")]
+pub mod panic;
+pub mod payload;
+pub mod readout;
+#[cfg(feature = "firmware")]
+pub mod rtc;
+#[cfg(feature = "firmware")]
+pub mod sensor;
+#[cfg(feature = "firmware")]
+pub mod stack;
+#[cfg(feature = "firmware")]
+pub mod websocket;
+pub mod websocket_codec;
+#[cfg(feature = "firmware")]
+pub mod wifi;
+
+#[cfg(feature = "firmware")]
+use embassy_rp::bind_interrupts;
+#[cfg(feature = "firmware")]
+use embassy_rp::peripherals::{I2C0, PIO0};
+#[cfg(feature = "firmware")]
+use embassy_time::Duration;
+
+/// The application timeout
+#[cfg(feature = "firmware")]
+pub const APP_TIMEOUT: Duration = Duration::from_secs(45);
+
+// Bind required interrupt handlers
+#[cfg(feature = "firmware")]
+bind_interrupts!(pub struct Irqs {
+    // PIO0 interrupt handler
+    PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
+    // ADC channel interrupt handler
+    ADC_IRQ_FIFO => embassy_rp::adc::InterruptHandler;
+    // I2C0 interrupt handler
+    I2C0_IRQ => embassy_rp::i2c::InterruptHandler<I2C0>;
+});
@@ -0,0 +1,318 @@
+//! Lifecycle state that persists across resets in the watchdog scratch registers
+
+use embassy_rp::pac::WATCHDOG;
+use embassy_time::Duration;
+
+/// Lifecycle hints that persist across resets
+#[derive(Debug, Clone, Copy)]
+pub struct Lifecycle;
+impl Lifecycle {
+    /// The watchdog has been started, but not much more happened
+    pub const WATCHDOG: u32 = 367300213;
+    /// The radio peripheral has been initialized
+    pub const RADIOINIT: u32 = 968074460;
+    /// The main application logic has been entered
+    pub const APPINIT: u32 = 3422455895;
+    /// The main application logic has finished
+    pub const LIGHTSLEEP: u32 = 156439317;
+    /// The device sleeps without booting the radio, as the battery is below the cutoff voltage
+    pub const CUTOFF: u32 = 2871640953;
+
+    /// Persists the current lifecycle
+    pub fn store(lifecycle: u32) {
+        WATCHDOG.scratch0().write_value(lifecycle);
+    }
+
+    /// Loads the last-persisted lifecycle
+    ///
+    /// # Note
+    /// The lifecycle values are random 32 bit constants, so validating against them is as good as a checksum.
+    pub fn load() -> Option<u32> {
+        let lifecycle = WATCHDOG.scratch0().read();
+        let known = [Self::WATCHDOG, Self::RADIOINIT, Self::APPINIT, Self::LIGHTSLEEP, Self::CUTOFF];
+        known.contains(&lifecycle).then_some(lifecycle)
+    }
+}
+
+/// The last published sensor reading that persists across resets
+#[derive(Debug, Clone, Copy)]
+pub struct LastReading {
+    /// The quantized sensor voltage in millivolts
+    pub millivolts: u16,
+}
+impl LastReading {
+    /// The maximum quantized sensor voltage (12 bit)
+    const MILLIVOLTS_MAX: u16 = 0x0FFF;
+    /// The scratch checksum XOR constant (8 bit)
+    const CHECKSUM_XOR: u8 = 0x3A;
+
+    /// Quantizes the given sensor voltage to millivolts
+    pub fn quantize(voltage: f64) -> u16 {
+        // Note: Float to int casts saturate, so negative values become zero
+        ((voltage * 1000.0 + 0.5) as u16).min(Self::MILLIVOLTS_MAX)
+    }
+
+    /// Persists the reading
+    pub fn store(self) {
+        // Note: The reading is packed into the lower half and its checksum into the third byte, as the highest byte
+        //  holds the watchdog starvation counter
+        let packed = self.millivolts.min(Self::MILLIVOLTS_MAX);
+        let checksum = Self::checksum(packed);
+        let shared = WATCHDOG.scratch1().read() & 0xFF00_0000;
+        WATCHDOG.scratch1().write_value(shared | ((checksum as u32) << 16) | packed as u32);
+    }
+
+    /// Loads the last-persisted reading, if any
+    pub fn load() -> Option<Self> {
+        let value = WATCHDOG.scratch1().read();
+        let (packed, checksum) = (value as u16, (value >> 16) as u8);
+        let true = checksum == Self::checksum(packed) else {
+            // No reading has been stored
+            return None;
+        };
+        Some(Self { millivolts: packed & Self::MILLIVOLTS_MAX })
+    }
+
+    /// Computes the 8 bit checksum of the given packed reading
+    const fn checksum(packed: u16) -> u8 {
+        (packed as u8) ^ ((packed >> 8) as u8) ^ Self::CHECKSUM_XOR
+    }
+}
+
+/// The sequence number of published readings that persists across resets
+///
+/// # Note
+/// The sequence number is 16 bit wide, so consumers must expect it to wrap around after 65536 published readings. It
+/// shares its scratch register with the panic counter.
+#[derive(Debug, Clone, Copy)]
+pub struct Sequence;
+impl Sequence {
+    /// The scratch checksum XOR constant (8 bit)
+    const CHECKSUM_XOR: u8 = 0x5E;
+
+    /// Persists the given sequence number
+    fn store(sequence: u16) {
+        // Note: The sequence number is stored in the middle two bytes and its checksum in the highest byte, as the
+        //  lowest byte holds the panic counter
+        let checksum = Self::checksum(sequence);
+        let shared = WATCHDOG.scratch2().read() & 0x0000_00FF;
+        WATCHDOG.scratch2().write_value(((checksum as u32) << 24) | ((sequence as u32) << 8) | shared);
+    }
+
+    /// Loads the last-persisted sequence number, if any
+    pub fn load() -> Option<u16> {
+        let value = WATCHDOG.scratch2().read();
+        let (sequence, checksum) = ((value >> 8) as u16, (value >> 24) as u8);
+        (checksum == Self::checksum(sequence)).then_some(sequence)
+    }
+
+    /// Computes the 8 bit checksum of the given sequence number
+    const fn checksum(sequence: u16) -> u8 {
+        (sequence as u8) ^ ((sequence >> 8) as u8) ^ Self::CHECKSUM_XOR
+    }
+
+    /// Increments and persists the sequence number, and returns the new value
+    ///
+    /// # Note
+    /// If no sequence number has been stored, the sequence starts at zero.
+    pub fn next() -> u16 {
+        let sequence = Self::load().map_or(0, |sequence| sequence.wrapping_add(1));
+        Self::store(sequence);
+        sequence
+    }
+}
+
+/// The amount of expired watchdog deadlines since the last published health summary that persists across resets
+///
+/// # Note
+/// The count is only 4 bit wide and saturates, as it shares its scratch register with the last published reading.
+#[derive(Debug, Clone, Copy)]
+pub struct Starvation;
+impl Starvation {
+    /// The maximum starvation count (4 bit)
+    const COUNT_MAX: u8 = 0x0F;
+    /// The scratch checksum XOR constant (4 bit)
+    const CHECKSUM_XOR: u8 = 0x06;
+
+    /// Persists the given starvation count
+    fn store(count: u8) {
+        // Note: The count and its checksum are packed into the highest byte, as the lower three bytes hold the last
+        //  published reading
+        let count = count.min(Self::COUNT_MAX);
+        let checksum = count ^ Self::CHECKSUM_XOR;
+        let shared = WATCHDOG.scratch1().read() & 0x00FF_FFFF;
+        WATCHDOG.scratch1().write_value(shared | ((checksum as u32) << 28) | ((count as u32) << 24));
+    }
+
+    /// Loads the last-persisted starvation count, or zero if no count has been stored
+    pub fn load() -> u8 {
+        let value = WATCHDOG.scratch1().read();
+        let (count, checksum) = ((value >> 24) as u8 & 0x0F, (value >> 28) as u8 & 0x0F);
+        match count == (checksum ^ Self::CHECKSUM_XOR) {
+            true => count,
+            false => 0,
+        }
+    }
+
+    /// Counts an expired watchdog deadline
+    pub fn bump() {
+        Self::store(Self::load().saturating_add(1));
+    }
+
+    /// Resets the starvation count after it has been published
+    pub fn reset() {
+        Self::store(0);
+    }
+}
+
+/// The battery voltage before a deep-sleep self-test that persists across resets
+///
+/// # Note
+/// The voltage is quantized to 10mV steps above 2.5V, so it fits into the upper half of the scratch register shared with
+/// the last panic checksum.
+#[derive(Debug, Clone, Copy)]
+pub struct SleepProbe;
+impl SleepProbe {
+    /// The scratch checksum XOR constant
+    const CHECKSUM_XOR: u8 = 0xA9;
+    /// The lowest representable voltage in millivolts
+    const BASE_MILLIVOLTS: i32 = 2500;
+    /// The quantization step in millivolts
+    const STEP_MILLIVOLTS: i32 = 10;
+    /// The interval between two self-tests
+    const INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Whether a self-test is due with the given wake count and sleep duration
+    ///
+    /// # Note
+    /// The interval is counted in wakes as there is no wall clock, so it is only approximately daily. The interval is
+    /// at most 86400 wakes and thus well below the wrap of the 24 bit [`WakeCounter`], so only one interval per wrap is
+    /// shortened.
+    pub fn is_due(wakes: u32, sleep: Duration) -> bool {
+        let cycles = (Self::INTERVAL.as_secs() / sleep.as_secs().max(1)).max(1) as u32;
+        wakes.is_multiple_of(cycles)
+    }
+
+    /// Persists the given battery voltage before sleeping
+    pub fn store(voltage: f64) {
+        // Note: Float to int casts saturate, so out-of-range voltages are clamped
+        let millivolts = (voltage * 1000.0 + 0.5) as i32;
+        let quantized = ((millivolts - Self::BASE_MILLIVOLTS) / Self::STEP_MILLIVOLTS).clamp(0, u8::MAX as i32) as u8;
+        let checksum = quantized ^ Self::CHECKSUM_XOR;
+        let shared = WATCHDOG.scratch3().read() & 0x0000_FFFF;
+        WATCHDOG.scratch3().write_value(((checksum as u32) << 24) | ((quantized as u32) << 16) | shared);
+    }
+
+    /// Loads and clears the persisted battery voltage in millivolts, if any
+    pub fn take() -> Option<i32> {
+        // Load and validate the voltage
+        let value = WATCHDOG.scratch3().read();
+        let (quantized, checksum) = ((value >> 16) as u8, (value >> 24) as u8);
+        let true = quantized == (checksum ^ Self::CHECKSUM_XOR) else {
+            // No voltage has been stored
+            return None;
+        };
+
+        // Invalidate checksum so that the self-test is evaluated only once
+        WATCHDOG.scratch3().write_value(value ^ 0xFF00_0000);
+        Some(Self::BASE_MILLIVOLTS + quantized as i32 * Self::STEP_MILLIVOLTS)
+    }
+}
+
+/// The free-running count of wakes that persists across resets
+///
+/// # Note
+/// The count shares scratch register 6 with the last panic location, so it is lost if the device panics, and must not
+/// be stored while a panic location is pending. It is 24 bit wide, so consumers must expect it to wrap around.
+#[derive(Debug, Clone, Copy)]
+pub struct WakeCounter;
+impl WakeCounter {
+    /// The maximum wake count (24 bit)
+    const COUNT_MAX: u32 = 0x00FF_FFFF;
+    /// The scratch checksum XOR constant (8 bit)
+    const CHECKSUM_XOR: u8 = 0x95;
+
+    /// Loads the last-persisted wake count, if any
+    pub fn load() -> Option<u32> {
+        let value = WATCHDOG.scratch6().read();
+        let (count, checksum) = (value & Self::COUNT_MAX, (value >> 24) as u8);
+        (checksum == Self::checksum(count)).then_some(count)
+    }
+
+    /// Increments and persists the wake count, and returns the new value
+    ///
+    /// # Note
+    /// If no count has been stored, the count starts at one, so an interval starts with the first wake.
+    pub fn next() -> u32 {
+        // Note: The count is packed into the lower three bytes, so the checksum fits into the highest byte
+        let count = Self::load().map_or(1, |count| (count + 1) & Self::COUNT_MAX);
+        WATCHDOG.scratch6().write_value(((Self::checksum(count) as u32) << 24) | count);
+        count
+    }
+
+    /// Computes the 8 bit checksum of the given count
+    const fn checksum(count: u32) -> u8 {
+        (count as u8) ^ ((count >> 8) as u8) ^ ((count >> 16) as u8) ^ Self::CHECKSUM_XOR
+    }
+}
+
+/// The running sum of sensor readings over multiple wakes that persists across resets
+///
+/// # Note
+/// The sum shares scratch register 7 with the tail of the last panic location, so it is lost if the device panics, and
+/// must not be stored while a panic location is pending.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadingAverage {
+    /// The sum of the quantized sensor voltages in millivolts
+    pub sum: u16,
+    /// The amount of accumulated readings
+    pub count: u16,
+}
+impl ReadingAverage {
+    /// The maximum amount of stored readings (4 bit)
+    pub const COUNT_MAX: u16 = 0x000F;
+    /// The scratch checksum XOR constant (12 bit)
+    const CHECKSUM_XOR: u32 = 0x0A6C;
+
+    /// Adds the given quantized reading
+    #[allow(clippy::should_implement_trait, reason = "this also counts the reading, so it is not a plain addition")]
+    pub fn add(self, millivolts: u16) -> Self {
+        // Note: At most `COUNT_MAX` 12 bit readings are stored, so the sum cannot overflow; we saturate anyway, so that a
+        //  corrupt sum yields a bogus average instead of a crash loop
+        Self { sum: self.sum.saturating_add(millivolts), count: self.count.saturating_add(1) }
+    }
+
+    /// The average sensor voltage
+    pub fn voltage(self) -> f64 {
+        (self.sum as f64) / (self.count.max(1) as f64) / 1000.0
+    }
+
+    /// Persists the running sum
+    pub fn store(self) {
+        // Note: The sum and count are packed into the upper 20 bits, so the checksum fits into the lower 12 bits
+        let packed = ((self.sum as u32) << 4) | self.count.min(Self::COUNT_MAX) as u32;
+        WATCHDOG.scratch7().write_value((packed << 12) | Self::checksum(packed));
+    }
+
+    /// Loads the last-persisted running sum, if any
+    pub fn load() -> Option<Self> {
+        let value = WATCHDOG.scratch7().read();
+        let packed = value >> 12;
+        let true = value & 0x0FFF == Self::checksum(packed) else {
+            // No running sum has been stored
+            return None;
+        };
+        Some(Self { sum: (packed >> 4) as u16, count: (packed as u16) & Self::COUNT_MAX })
+    }
+
+    /// Clears the last-persisted running sum
+    pub fn clear() {
+        // Note: The checksum of zero is never zero, so this invalidates the register
+        WATCHDOG.scratch7().write_value(0);
+    }
+
+    /// Computes the 12 bit checksum of the given packed sum and count
+    const fn checksum(packed: u32) -> u32 {
+        (packed ^ (packed >> 12) ^ Self::CHECKSUM_XOR) & 0x0FFF
+    }
+}
@@ -0,0 +1,655 @@
+//! MQTT client
+
+use crate::APP_TIMEOUT;
+use crate::budget::AwakeBudget;
+use crate::config::{AppConfig, MqttQos};
+use crate::debug_println;
+use crate::mqtt_codec::{self, MqttBuffer, MqttError, PacketBuffer};
+use crate::payload::Payload;
+use crate::websocket::WebSocket;
+use core::fmt::Display;
+use core::net::SocketAddr;
+use embassy_net::tcp::client::{TcpClient, TcpClientState, TcpConnection};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline};
+use embedded_io_async::{Error, ErrorKind, ErrorType, Read, Write};
+use embedded_nal_async::TcpConnect;
+use mqtt_tiny::coding::Decoder;
+use mqtt_tiny::error::{Data, DecoderError};
+use mqtt_tiny::packets::TryFromIterator;
+use mqtt_tiny::{Connack, Connect, Disconnect, Puback, Pubcomp, Publish, Pubrec, Subscribe, err};
+
+/// The maximum time to wait for the server to respond, so we can give up before the watchdog kicks in
+const RESPONSE_TIMEOUT: Duration = Duration::from_ticks(APP_TIMEOUT.as_ticks() / 5);
+/// The buffer size for the birth message payload
+const BIRTH_BUF_SIZE: usize = 128;
+/// The buffer size for the device ID within topics
+const DEVICE_BUF_SIZE: usize = 32;
+/// The buffer size for plain value payloads
+const METRIC_BUF_SIZE: usize = 64;
+
+/// Per-boot startup information for the birth message
+#[derive(Debug, Clone, Copy)]
+pub struct BirthInfo<'a> {
+    /// The firmware version
+    pub version: &'a str,
+    /// The reason of the last reset
+    pub reset: &'a str,
+    /// The sequence number of the reading published with this boot
+    pub seq: u16,
+    /// The IP address, if any
+    pub ip: Option<Ipv4Address>,
+}
+
+/// An MQTT `SUBACK` packet for a single topic
+///
+/// # Note
+/// `mqtt-tiny` decodes `SUBACK` packets like a plain ack without return codes, which rejects every real `SUBACK`.
+struct Suback {
+    /// The packet ID
+    packet_id: u16,
+    /// The return code for the subscribed topic
+    return_code: u8,
+}
+impl Suback {
+    /// The packet type
+    const TYPE: u8 = 9;
+    /// The return code for a failed subscription
+    const FAILURE: u8 = 0x80;
+}
+impl TryFromIterator for Suback {
+    fn try_from_iter<T>(iter: T) -> Result<Self, DecoderError>
+    where
+        T: IntoIterator<Item = u8>,
+    {
+        // Decode header and length
+        let mut decoder = Decoder::new(iter);
+        let (Self::TYPE, _flags) = decoder.header()? else {
+            return Err(err!(Data::SpecViolation, "invalid packet type"))?;
+        };
+        let 3 = decoder.packetlen()? else {
+            return Err(err!(Data::SpecViolation, "unexpected suback length"))?;
+        };
+
+        // Decode body
+        let packet_id = decoder.u16()?;
+        let return_code = decoder.u8()?;
+        Ok(Self { packet_id, return_code })
+    }
+}
+
+/// An MQTT `PUBREL` packet
+///
+/// # Note
+/// `mqtt-tiny` encodes `PUBREL` packets with zero header flags, but the specification requires `0b0010`, and the server
+/// must close the connection otherwise.
+struct Pubrel {
+    /// The packet ID
+    packet_id: u16,
+}
+impl Pubrel {
+    /// The packet type
+    const TYPE: u8 = 6;
+    /// The required header flags
+    const FLAGS: u8 = 0b0010;
+}
+impl IntoIterator for Pubrel {
+    type Item = u8;
+    type IntoIter = core::array::IntoIter<u8, 4>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        // Encode header, length and packet ID
+        let [id_hi, id_lo] = self.packet_id.to_be_bytes();
+        [(Self::TYPE << 4) | Self::FLAGS, 2, id_hi, id_lo].into_iter()
+    }
+}
+
+/// An incoming MQTT packet within an established session
+#[allow(clippy::large_enum_variant, reason = "packets are short-lived and are not stored")]
+enum Incoming {
+    /// A `SUBACK` packet
+    Suback(Suback),
+    /// A `PUBLISH` packet
+    Publish(Publish),
+    /// A `PUBACK` packet
+    Puback(Puback),
+    /// A `PUBREC` packet
+    Pubrec(Pubrec),
+    /// A `PUBCOMP` packet
+    Pubcomp(Pubcomp),
+}
+impl TryFromIterator for Incoming {
+    fn try_from_iter<T>(iter: T) -> Result<Self, DecoderError>
+    where
+        T: IntoIterator<Item = u8>,
+    {
+        // Peek at the header to determine the type
+        let mut iter = iter.into_iter().peekable();
+        let header = iter.peek().ok_or(err!(Data::Truncated, "empty packet"))?;
+        match header >> 4 {
+            Suback::TYPE => Suback::try_from_iter(&mut iter).map(Self::Suback),
+            Publish::TYPE => Publish::try_from_iter(&mut iter).map(Self::Publish),
+            Puback::TYPE => Puback::try_from_iter(&mut iter).map(Self::Puback),
+            Pubrec::TYPE => Pubrec::try_from_iter(&mut iter).map(Self::Pubrec),
+            Pubcomp::TYPE => Pubcomp::try_from_iter(&mut iter).map(Self::Pubcomp),
+            _ => Err(err!(Data::SpecViolation, "unexpected packet type"))?,
+        }
+    }
+}
+
+/// MQTT stack with TCP and MQTT buffers of `N` bytes each
+pub struct MqttStack<const N: usize> {
+    /// The associated network stack
+    network: Stack<'static>,
+    /// The TCP connection state
+    tcp_state: TcpClientState<1, N, N>,
+}
+impl<const N: usize> MqttStack<N> {
+    /// Creates a new MQTT handle and associated state
+    pub const fn new(network: Stack<'static>) -> Self {
+        // Create state and init self
+        let tcp_state = TcpClientState::new();
+        Self { network, tcp_state }
+    }
+
+    /// Creates an MQTT client for the given device ID, but does not connect yet, or fails with
+    /// [`MqttError::InvalidAddress`] if the configured server address is malformed
+    pub fn init<D>(&mut self, config: &AppConfig, device: D) -> Result<MqttClient<'_, N>, MqttError>
+    where
+        D: Display,
+    {
+        // Parse the MQTT address once, and create the TCP client
+        let address: SocketAddr = config.MQTT_ADDR.parse().map_err(|_| MqttError::InvalidAddress(config.MQTT_ADDR))?;
+        let tcp_client = TcpClient::new(self.network, &self.tcp_state);
+        let device = MqttBuffer::from_display(device);
+        Ok(MqttClient { tcp_client, address, config: *config, device })
+    }
+
+    /// Connects to the MQTT server, publishes a single message with the configured quality-of-service level, and
+    /// disconnects again
+    ///
+    /// # Note
+    /// This is a convenience for simple one-shot publishes; use [`Self::init`] for the granular API.
+    pub async fn publish_once<D>(
+        &mut self,
+        config: &AppConfig,
+        device: D,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<(), MqttError>
+    where
+        D: Display,
+    {
+        let client = self.init(config, device)?;
+        let mut session = client.connect().await?.login().await?;
+        session.publish(topic, payload).await?;
+        session.disconnect().await
+    }
+}
+
+/// An [`MQTT`] client
+pub struct MqttClient<'a, const N: usize> {
+    /// The TCP clieny connection pool
+    tcp_client: TcpClient<'a, 1, N, N>,
+    /// MQTT server address
+    address: SocketAddr,
+    /// [`Config`]
+    config: AppConfig,
+    /// The device ID for topics
+    device: MqttBuffer<DEVICE_BUF_SIZE>,
+}
+impl<'a, const N: usize> MqttClient<'a, N> {
+    /// Connects to the MQTT server, or fails if the connection is not established within [`RESPONSE_TIMEOUT`]
+    pub async fn connect(&'a self) -> Result<MqttTcpConnection<'a, N>, MqttError> {
+        let link = self.link().await?;
+        Ok(MqttConnection::new(MqttTransport { client: self, link: Some(link) }, self.config, self.device))
+    }
+
+    /// Establishes the TCP connection and the optional WebSocket tunnel, or fails if the connection is not established
+    /// within [`RESPONSE_TIMEOUT`]
+    async fn link(&'a self) -> Result<MqttLink<'a, N>, MqttError> {
+        // Connect to the MQTT server
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let connection = with_deadline(deadline, self.tcp_client.connect(self.address)).await;
+        let connection = connection.map_err(|_| MqttError::Timeout)?.map_err(|_| MqttError::Connect)?;
+        if self.config.MQTT_WS.is_empty() {
+            // Use the plain TCP connection
+            return Ok(MqttLink::Tcp(connection));
+        }
+
+        // Upgrade the connection to WebSocket
+        let websocket = WebSocket::connect(connection, self.config.MQTT_ADDR, self.config.MQTT_WS);
+        let websocket = with_deadline(deadline, websocket).await;
+        let websocket = websocket.map_err(|_| MqttError::Timeout)?.map_err(|_| MqttError::Connect)?;
+        Ok(MqttLink::WebSocket(websocket))
+    }
+}
+
+/// A transport that can re-establish a dropped connection to the MQTT server
+#[allow(async_fn_in_trait, reason = "the firmware is single-threaded, so the future needs no `Send` bound")]
+pub trait Reconnect: Read + Write {
+    /// Closes the current connection, and connects to the MQTT server again
+    async fn reconnect(&mut self) -> Result<(), MqttError>;
+}
+
+/// A TCP connection, optionally tunneled via WebSocket
+enum MqttLink<'a, const N: usize> {
+    /// A plain TCP connection
+    Tcp(TcpConnection<'a, 1, N, N>),
+    /// A WebSocket connection over TCP
+    WebSocket(WebSocket<TcpConnection<'a, 1, N, N>>),
+}
+
+/// An MQTT transport over a TCP connection, optionally tunneled via WebSocket
+pub struct MqttTransport<'a, const N: usize> {
+    /// The client to reconnect with
+    client: &'a MqttClient<'a, N>,
+    /// The current connection, or `None` if reconnecting has failed
+    link: Option<MqttLink<'a, N>>,
+}
+impl<const N: usize> ErrorType for MqttTransport<'_, N> {
+    type Error = ErrorKind;
+}
+impl<const N: usize> Read for MqttTransport<'_, N> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match &mut self.link {
+            Some(MqttLink::Tcp(tcp)) => tcp.read(buf).await.map_err(|e| e.kind()),
+            Some(MqttLink::WebSocket(websocket)) => websocket.read(buf).await,
+            None => Err(ErrorKind::NotConnected),
+        }
+    }
+}
+impl<const N: usize> Write for MqttTransport<'_, N> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match &mut self.link {
+            Some(MqttLink::Tcp(tcp)) => tcp.write(buf).await.map_err(|e| e.kind()),
+            Some(MqttLink::WebSocket(websocket)) => websocket.write(buf).await,
+            None => Err(ErrorKind::NotConnected),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        match &mut self.link {
+            Some(MqttLink::Tcp(tcp)) => tcp.flush().await.map_err(|e| e.kind()),
+            Some(MqttLink::WebSocket(websocket)) => websocket.flush().await,
+            None => Err(ErrorKind::NotConnected),
+        }
+    }
+}
+impl<const N: usize> Reconnect for MqttTransport<'_, N> {
+    async fn reconnect(&mut self) -> Result<(), MqttError> {
+        // Drop the broken connection first, as the TCP connection pool only has room for a single connection
+        self.link = None;
+        self.link = Some(self.client.link().await?);
+        Ok(())
+    }
+}
+
+/// An MQTT connection over a TCP connection, optionally tunneled via WebSocket
+pub type MqttTcpConnection<'a, const N: usize> = MqttConnection<MqttTransport<'a, N>, N>;
+
+/// A buffered MQTT connection over an arbitrary byte stream transport
+pub struct MqttConnection<T, const N: usize> {
+    /// [`Config`]
+    config: AppConfig,
+    /// The device ID for topics
+    device: MqttBuffer<DEVICE_BUF_SIZE>,
+    /// The underlying transport
+    transport: T,
+    /// The receive buffer
+    packets: PacketBuffer<N>,
+}
+impl<T, const N: usize> MqttConnection<T, N>
+where
+    T: Read + Write,
+{
+    /// Creates a new MQTT connection for the given device ID over the given, already connected transport
+    pub const fn new(transport: T, config: AppConfig, device: MqttBuffer<DEVICE_BUF_SIZE>) -> Self {
+        Self { config, device, transport, packets: PacketBuffer::new() }
+    }
+
+    /// Attempts to login to establish a MQTT application-layer session, or fails if the server rejects the login or does
+    /// not respond within [`RESPONSE_TIMEOUT`]
+    pub async fn login(mut self) -> Result<MqttSession<T, N>, MqttError> {
+        self.handshake().await?;
+        Ok(MqttSession { connection: self, next_packet_id: 1, reconnected: false })
+    }
+
+    /// Sends the MQTT connect packet and validates the server response
+    async fn handshake(&mut self) -> Result<(), MqttError> {
+        // Build MQTT connect packet
+        let keepalive = u16::try_from(self.config.MQTT_KEEPALIVE.as_secs()).unwrap_or(u16::MAX);
+        // Note: The prefix doubles as client id, which must be stable for the server to resume a persistent session
+        let mut connect = Connect::new(keepalive, self.config.MQTT_CLEAN, self.config.MQTT_PRFX)
+            .expect("failed to assemble mqtt connect packet");
+        if self.config.MQTT_USER.len() + self.config.MQTT_PASS.len() > 0 {
+            // Set username and password if configured
+            connect = (connect.with_username_password(self.config.MQTT_USER, self.config.MQTT_PASS))
+                .expect("failed to assemble mqtt connect packet");
+        }
+        if !self.config.MQTT_AVAIL.is_empty() {
+            // Let the server report us as offline if the session is lost ungracefully
+            let topic = self.publish_topic(self.config.MQTT_AVAIL)?;
+            connect = (connect.with_will(topic, b"offline", 0, true)).expect("failed to assemble mqtt connect packet");
+        }
+
+        // Send connect packet and await/validate connack packet
+        self.send(connect, true).await?;
+        let connack = self.recv::<Connack>(Instant::now() + RESPONSE_TIMEOUT).await?;
+        match connack.return_code() {
+            0 if !self.config.MQTT_CLEAN && !connack.session_present() => {
+                // The server has no session for our client id yet (or has expired it), so it starts a new one
+                debug_println!("[info] mqtt server started a new persistent session");
+                Ok(())
+            }
+            0 => Ok(()),
+            3 => Err(MqttError::Unavailable),
+            4 => Err(MqttError::BadCredentials),
+            5 => Err(MqttError::NotAuthorized),
+            code => Err(MqttError::Rejected(code)),
+        }
+    }
+
+    /// Assembles the full topic for the given topic name by expanding the topic template, or fails if it does not fit
+    /// into the buffer
+    fn topic(&self, topic: &str) -> Result<MqttBuffer<N>, MqttError> {
+        mqtt_codec::topic(self.config.MQTT_TOPIC_FMT, self.config.MQTT_PRFX, &self.device, topic)
+    }
+
+    /// Assembles the full topic for the given topic name, or fails if it is not a valid publish topic
+    fn publish_topic(&self, topic: &str) -> Result<MqttBuffer<N>, MqttError> {
+        mqtt_codec::publish_topic(self.config.MQTT_TOPIC_FMT, self.config.MQTT_PRFX, &self.device, topic)
+    }
+
+    /// Sends an MQTT packet, or fails without sending anything if the packet exceeds the buffer size
+    async fn send<Packet>(&mut self, packet: Packet, flush: bool) -> Result<(), MqttError>
+    where
+        Packet: IntoIterator<Item = u8>,
+    {
+        mqtt_codec::send::<N, _, _>(&mut self.transport, packet, flush).await
+    }
+
+    /// Receives an MQTT packet, or fails if no packet has been received until the deadline
+    async fn recv<Packet>(&mut self, deadline: Instant) -> Result<Packet, MqttError>
+    where
+        Packet: TryFromIterator,
+    {
+        // Read until a complete packet is available
+        let packet = with_deadline(deadline, self.packets.recv(&mut self.transport)).await;
+        match packet.map_err(|_| MqttError::Timeout)? {
+            Err(MqttError::TooLarge) => {
+                // Note: The oversized packet has already been discarded, so the session is still usable
+                debug_println!("[warn] discarding oversized mqtt packet");
+                Err(MqttError::TooLarge)
+            }
+            packet => packet,
+        }
+    }
+}
+
+/// An established MQTT connection
+pub struct MqttSession<T, const N: usize> {
+    /// The MQTT connection
+    connection: MqttConnection<T, N>,
+    /// The next packet ID
+    next_packet_id: u16,
+    /// Whether the session has already been re-established after a dropped connection
+    reconnected: bool,
+}
+impl<T, const N: usize> MqttSession<T, N>
+where
+    T: Reconnect,
+{
+    /// Publishes an MQTT message with the configured quality-of-service level
+    pub async fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), MqttError> {
+        let qos = self.connection.config.MQTT_QOS;
+        self.publish_with_qos(topic, payload, false, qos).await
+    }
+
+    /// Publishes the given value as plain text MQTT message with the configured quality-of-service level
+    ///
+    /// # Panics
+    /// This function panics if the formatted value exceeds the plain value payload buffer.
+    pub async fn publish_metric<V>(&mut self, topic: &str, value: V) -> Result<(), MqttError>
+    where
+        V: Display,
+    {
+        let value = MqttBuffer::<METRIC_BUF_SIZE>::from_display(value);
+        self.publish(topic, &value).await
+    }
+
+    /// Publishes a retained MQTT message with the configured quality-of-service level
+    pub async fn publish_retained(&mut self, topic: &str, payload: &[u8]) -> Result<(), MqttError> {
+        let qos = self.connection.config.MQTT_QOS;
+        self.publish_with_qos(topic, payload, true, qos).await
+    }
+
+    /// Publishes an MQTT message with the given quality-of-service level, or fails if an acknowledged message is not
+    /// acknowledged within [`RESPONSE_TIMEOUT`]
+    ///
+    /// # Note
+    /// QoS 1 takes one round trip for the `PUBACK`, and QoS 2 takes two round trips for the `PUBREC` and `PUBCOMP`.
+    /// Messages on subscribed topics that arrive while waiting for the acknowledgements are discarded. If the server has
+    /// dropped the connection and `MQTT_RECONNECT` is set, the session is re-established once per session, and the
+    /// message is published again.
+    pub async fn publish_with_qos(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        retain: bool,
+        qos: MqttQos,
+    ) -> Result<(), MqttError> {
+        match self.try_publish(topic, payload, retain, qos).await {
+            Err(MqttError::Io | MqttError::Closed) if self.connection.config.MQTT_RECONNECT && !self.reconnected => {
+                // Reconnect and retry once
+                debug_println!("[warn] mqtt connection dropped mid-session; reconnecting");
+                self.reconnect().await?;
+                self.try_publish(topic, payload, retain, qos).await
+            }
+            result => result,
+        }
+    }
+
+    /// Re-establishes the session after the server has dropped the connection
+    ///
+    /// # Note
+    /// Unless `MQTT_CLEAN` is disabled, the session is not persistent, so subscriptions are lost. As the server has
+    /// probably published the will, the availability is reported again.
+    async fn reconnect(&mut self) -> Result<(), MqttError> {
+        // Reconnect and login again
+        self.reconnected = true;
+        self.connection.transport.reconnect().await?;
+        self.connection.packets = PacketBuffer::new();
+        self.connection.handshake().await?;
+
+        // Restore the availability if configured
+        let (avail, qos) = (self.connection.config.MQTT_AVAIL, self.connection.config.MQTT_QOS);
+        if !avail.is_empty() {
+            self.try_publish(avail, b"online", true, qos).await?;
+        }
+        Ok(())
+    }
+
+    /// Publishes an MQTT message with the given quality-of-service level without reconnecting; see
+    /// [`Self::publish_with_qos`]
+    async fn try_publish(&mut self, topic: &str, payload: &[u8], retain: bool, qos: MqttQos) -> Result<(), MqttError> {
+        // Skip the message if we are out of time
+        if AwakeBudget::is_exhausted() {
+            return Err(MqttError::OverBudget);
+        }
+
+        // Assemble message
+        let topic = self.connection.publish_topic(topic)?;
+        let publish = Publish::new(topic, payload, retain).map_err(|_| MqttError::TooLarge)?;
+        let (MqttQos::AtLeastOnce | MqttQos::ExactlyOnce) = qos else {
+            // Note: QoS 0 does not expect a puback message
+            return self.connection.send(publish, false).await;
+        };
+
+        // Publish message and wait for the acknowledgement
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let packet_id = self.next_packet_id();
+        self.connection.send(publish.with_qos(qos as u8, packet_id, false), true).await?;
+        if qos == MqttQos::AtLeastOnce {
+            return self.recv_ack(Puback::TYPE, packet_id, deadline).await;
+        }
+
+        // Release the message and wait for the completion
+        self.recv_ack(Pubrec::TYPE, packet_id, deadline).await?;
+        self.connection.send(Pubrel { packet_id }, true).await?;
+        self.recv_ack(Pubcomp::TYPE, packet_id, deadline).await
+    }
+
+    /// Waits for the acknowledgement of the given type for the given packet ID, or fails if it does not arrive until the
+    /// deadline
+    async fn recv_ack(&mut self, ack_type: u8, packet_id: u16, deadline: Instant) -> Result<(), MqttError> {
+        loop {
+            // Note: Oversized packets have already been discarded, so they are skipped like other unrelated messages
+            let incoming = match self.connection.recv::<Incoming>(deadline).await {
+                Err(MqttError::TooLarge) => continue,
+                incoming => incoming?,
+            };
+            match (incoming, ack_type) {
+                (Incoming::Puback(ack), Puback::TYPE) if ack.packet_id() == packet_id => break Ok(()),
+                (Incoming::Pubrec(ack), Pubrec::TYPE) if ack.packet_id() == packet_id => break Ok(()),
+                (Incoming::Pubcomp(ack), Pubcomp::TYPE) if ack.packet_id() == packet_id => break Ok(()),
+                // Log warning and continue
+                (Incoming::Publish(_), _) => debug_println!("[warn] discarding mqtt message while waiting for ack"),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Publishes the given startup information as one-shot birth message to the `birth` topic
+    ///
+    /// # Note
+    /// Unlike the availability, the birth message is not retained, so every boot yields a distinct event. The payload is
+    /// always structured, so it uses JSON unless CBOR has been selected explicitly.
+    pub async fn birth(&mut self, info: &BirthInfo<'_>) -> Result<(), MqttError> {
+        // Serialize the startup information, and omit unavailable values
+        let format = self.connection.config.MQTT_FORMAT.structured();
+        let mut payload = Payload::<BIRTH_BUF_SIZE>::new(format);
+        payload.text("version", info.version).text("reset", info.reset).unsigned("seq", info.seq as u64);
+        if let Some(ip) = info.ip {
+            // Note: An IPv4 address is at most 15 bytes
+            let ip = MqttBuffer::<16>::from_display(ip);
+            payload.text("ip", str::from_utf8(&ip).unwrap_or_default());
+        }
+
+        // Publish the message
+        self.publish("birth", &payload.finish()?).await
+    }
+
+    /// Subscribes to an MQTT topic with QoS 0
+    ///
+    /// # Note
+    /// This does not wait for the `SUBACK`, as the server may send matching messages before it; use
+    /// [`Self::next_message`] to receive them.
+    pub async fn subscribe(&mut self, topic: &str) -> Result<(), MqttError> {
+        self.send_subscribe(topic).await?;
+        Ok(())
+    }
+
+    /// Sends a subscribe packet for an MQTT topic with QoS 0, and returns its packet ID
+    async fn send_subscribe(&mut self, topic: &str) -> Result<u16, MqttError> {
+        // Send subscribe packet
+        let packet_id = self.next_packet_id();
+        let topic = self.topic(topic)?;
+        let subscribe = Subscribe::new(packet_id, [(&*topic, 0)]).expect("failed to assemble mqtt subscribe packet");
+        self.connection.send(subscribe, true).await?;
+        Ok(packet_id)
+    }
+
+    /// Waits for the next message on a subscribed topic, or returns `None` if no message arrives within the timeout
+    pub async fn next_message(&mut self, timeout: Duration) -> Option<Publish> {
+        self.next_message_until(Instant::now() + timeout).await
+    }
+
+    /// Publishes a request and waits for the response on the given response topic, or fails if the server rejects the
+    /// subscription to the response topic, or if no response arrives within the timeout
+    ///
+    /// # Note
+    /// The request is only published once the server has acknowledged the subscription, so we cannot miss an immediate
+    /// response, and a rejected subscription fails fast with [`MqttError::SubscribeRejected`] instead of a timeout.
+    /// Messages that arrive before the acknowledgement predate the request, and are discarded like messages on other
+    /// subscribed topics.
+    pub async fn request(
+        &mut self,
+        request_topic: &str,
+        payload: &[u8],
+        response_topic: &str,
+        timeout: Duration,
+    ) -> Result<MqttBuffer<N>, MqttError> {
+        // Subscribe to the response topic and wait for the acknowledgement
+        let deadline = Instant::now() + timeout;
+        let packet_id = self.send_subscribe(response_topic).await?;
+        loop {
+            // Note: Oversized packets have already been discarded, so they are skipped like other unrelated messages
+            let incoming = match self.connection.recv::<Incoming>(deadline).await {
+                Err(MqttError::TooLarge) => continue,
+                incoming => incoming?,
+            };
+            match incoming {
+                Incoming::Suback(suback) if suback.packet_id != packet_id => continue,
+                Incoming::Suback(suback) if suback.return_code == Suback::FAILURE => {
+                    return Err(MqttError::SubscribeRejected);
+                }
+                Incoming::Suback(_) => break,
+                Incoming::Publish(_) => debug_println!("[warn] discarding mqtt message while waiting for suback"),
+                Incoming::Puback(_) | Incoming::Pubrec(_) | Incoming::Pubcomp(_) => continue,
+            }
+        }
+
+        // Publish the request, and wait for the response
+        self.publish(request_topic, payload).await?;
+        self.connection.transport.flush().await.map_err(|_| MqttError::Io)?;
+        let response_topic = self.topic(response_topic)?;
+        loop {
+            let message = self.next_message_until(deadline).await.ok_or(MqttError::Timeout)?;
+            if message.topic() == &*response_topic {
+                break Ok(message.payload().iter().copied().collect());
+            }
+
+            // Log warning and continue
+            debug_println!("[warn] discarding unrelated mqtt message while waiting for response");
+        }
+    }
+
+    /// Waits for the next message on a subscribed topic, or returns `None` if no message arrives until the deadline
+    async fn next_message_until(&mut self, deadline: Instant) -> Option<Publish> {
+        // Skip any subscription acknowledgements and oversized messages
+        loop {
+            let incoming = match self.connection.recv::<Incoming>(deadline).await {
+                Err(MqttError::TooLarge) => continue,
+                incoming => incoming.ok()?,
+            };
+            match incoming {
+                Incoming::Publish(publish) => break Some(publish),
+                Incoming::Suback(suback) if suback.return_code == Suback::FAILURE => {
+                    // Log warning and continue
+                    debug_println!("[warn] mqtt subscription was rejected: {}", suback.packet_id);
+                }
+                Incoming::Suback(_) | Incoming::Puback(_) | Incoming::Pubrec(_) | Incoming::Pubcomp(_) => continue,
+            }
+        }
+    }
+
+    /// Allocates the next packet ID
+    fn next_packet_id(&mut self) -> u16 {
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        packet_id
+    }
+
+    /// Assembles the full topic for the given topic name, or fails if it does not fit into the buffer
+    pub fn topic(&self, topic: &str) -> Result<MqttBuffer<N>, MqttError> {
+        self.connection.topic(topic)
+    }
+
+    /// Terminates the MQTT session
+    pub async fn disconnect(mut self) -> Result<(), MqttError> {
+        // Send a disconnect packet to terminate the MQTT session
+        let disconnect = Disconnect::new();
+        self.connection.send(disconnect, true).await
+    }
+}
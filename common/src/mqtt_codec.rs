@@ -0,0 +1,522 @@
+//! MQTT packet framing and topic assembly, independent of the network stack, so it can be tested on the host
+
+use core::fmt::{Display, Formatter};
+use core::ops::Deref;
+use embedded_io_async::{Read, Write};
+use mqtt_tiny::error::Decoding;
+use mqtt_tiny::packets::TryFromIterator;
+
+/// The maximum amount of decimals for formatted floats
+pub const DECIMALS_MAX: u16 = 9;
+
+/// An MQTT connection error
+#[derive(Debug, Clone, Copy)]
+pub enum MqttError {
+    /// The TCP connection could not be established
+    Connect,
+    /// The server closed the connection
+    Closed,
+    /// The connection failed while reading or writing
+    Io,
+    /// The server rejected the login due to invalid credentials
+    BadCredentials,
+    /// The server rejected the login as the client is not authorized
+    NotAuthorized,
+    /// The server is temporarily unavailable
+    Unavailable,
+    /// The server rejected the login with the given return code
+    Rejected(u8),
+    /// The server rejected a subscription, e.g. as the client is not authorized for the topic
+    SubscribeRejected,
+    /// The topic does not fit into the buffer, or contains wildcards or null characters, which are not allowed for
+    /// publishing
+    InvalidTopic,
+    /// The packet exceeds the buffer size, so it has not been sent
+    TooLarge,
+    /// The server did not respond in time
+    Timeout,
+    /// The awake budget is exhausted, so the remaining messages are skipped
+    OverBudget,
+    /// The configured server address is not a valid `<ip>:<port>` socket address
+    InvalidAddress(&'static str),
+    /// The server sent a malformed packet
+    Protocol,
+}
+impl Display for MqttError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Connect => write!(f, "failed to connect to mqtt server"),
+            Self::Closed => write!(f, "mqtt server closed the connection"),
+            Self::Io => write!(f, "failed to communicate with mqtt server"),
+            Self::BadCredentials => write!(f, "failed to login to mqtt server: bad username or password"),
+            Self::NotAuthorized => write!(f, "failed to login to mqtt server: not authorized"),
+            Self::Unavailable => write!(f, "failed to login to mqtt server: server unavailable"),
+            Self::Rejected(code) => write!(f, "failed to login to mqtt server: return code {code}"),
+            Self::SubscribeRejected => write!(f, "mqtt server rejected the subscription"),
+            Self::InvalidTopic => write!(f, "invalid mqtt topic: too large, or contains wildcards or null characters"),
+            Self::TooLarge => write!(f, "mqtt packet is too large"),
+            Self::Timeout => write!(f, "timed out waiting for mqtt server"),
+            Self::OverBudget => write!(f, "awake budget exhausted; skipping remaining mqtt messages"),
+            Self::InvalidAddress(address) => write!(f, "invalid mqtt server address: \"{address}\""),
+            Self::Protocol => write!(f, "mqtt server sent a malformed packet"),
+        }
+    }
+}
+
+impl MqttError {
+    /// Whether the error is caused by the device config, so that retrying is pointless until the config is fixed
+    pub const fn is_config_error(self) -> bool {
+        matches!(
+            self,
+            Self::BadCredentials
+                | Self::NotAuthorized
+                | Self::Rejected(_)
+                | Self::SubscribeRejected
+                | Self::InvalidTopic
+                | Self::InvalidAddress(_)
+        )
+    }
+
+    /// Whether the error only affects a single packet that has not been sent or has been discarded on receipt, so the
+    /// session is still usable and only the affected message needs to be skipped
+    pub const fn is_packet_error(self) -> bool {
+        matches!(self, Self::TooLarge)
+    }
+}
+
+/// A buffer to serialize values in contigous memory with a capacity of `N` bytes
+#[derive(Debug, Clone, Copy)]
+pub struct MqttBuffer<const N: usize> {
+    /// The underlying buffer
+    buf: [u8; N],
+    /// The buffer length
+    len: usize,
+}
+impl<const N: usize> MqttBuffer<N> {
+    /// Creates a new, empty MQTT buffer
+    #[allow(clippy::new_without_default, reason = "buffers are always created explicitly")]
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    /// Creates a new buffer from the given value by formatting it as string
+    ///
+    /// # Panics
+    /// This function panics if the formatted value exceeds the buffer capacity; see [`Self::try_from_display`].
+    pub fn from_display<T>(value: T) -> Self
+    where
+        T: Display,
+    {
+        Self::try_from_display(value).expect("display value is too large")
+    }
+
+    /// Creates a new buffer from the given float by formatting it as string with a fixed amount of decimals (capped to
+    /// [`DECIMALS_MAX`])
+    ///
+    /// # Panics
+    /// This function panics if the formatted value exceeds the buffer capacity; see [`Self::try_from_display`].
+    pub fn from_float(value: f64, decimals: u16) -> Self {
+        let decimals = decimals.min(DECIMALS_MAX) as usize;
+        Self::from_display(format_args!("{value:.decimals$}"))
+    }
+
+    /// Creates a new buffer from the given value by formatting it as string, or fails if the formatted value exceeds the
+    /// buffer capacity
+    pub fn try_from_display<T>(value: T) -> Result<Self, core::fmt::Error>
+    where
+        T: Display,
+    {
+        use core::fmt::Write;
+
+        // Allocate self and format value
+        let mut this = Self { buf: [0; N], len: 0 };
+        write!(&mut this, "{value}")?;
+        Ok(this)
+    }
+
+    /// Collects the given bytes, or fails if they exceed the buffer capacity
+    pub fn try_from_iter<Bytes>(bytes: Bytes) -> Result<Self, core::fmt::Error>
+    where
+        Bytes: IntoIterator<Item = u8>,
+    {
+        // Collect bytes
+        let mut this = Self { buf: [0; N], len: 0 };
+        for byte in bytes {
+            // Collect bytes into ad-hoc buffer since we need a slice
+            let slot = this.buf.get_mut(this.len).ok_or(core::fmt::Error)?;
+            *slot = byte;
+            this.len += 1;
+        }
+        Ok(this)
+    }
+
+    /// Appends the given bytes, or fails if they exceed the remaining buffer capacity
+    pub fn try_extend(&mut self, bytes: &[u8]) -> Result<(), core::fmt::Error> {
+        // Allocate target slice
+        let remaining = &mut self.buf[self.len..];
+        let target = remaining.get_mut(..bytes.len()).ok_or(core::fmt::Error)?;
+
+        // Copy data and increment length
+        target.copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+impl<const N: usize> AsRef<[u8]> for MqttBuffer<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+impl<const N: usize> Deref for MqttBuffer<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf[..self.len]
+    }
+}
+impl<const N: usize> core::fmt::Write for MqttBuffer<N> {
+    fn write_str(&mut self, str_: &str) -> core::fmt::Result {
+        self.try_extend(str_.as_bytes())
+    }
+}
+impl<const N: usize> FromIterator<u8> for MqttBuffer<N> {
+    fn from_iter<Bytes>(bytes: Bytes) -> Self
+    where
+        Bytes: IntoIterator<Item = u8>,
+    {
+        Self::try_from_iter(bytes).expect("source iterator is too large")
+    }
+}
+
+/// Assembles the full topic for the given topic name by expanding the topic template with the given prefix and device
+/// ID, or fails if it does not fit into the buffer
+pub fn topic<const N: usize>(
+    template: &str,
+    prefix: &str,
+    device: &[u8],
+    name: &str,
+) -> Result<MqttBuffer<N>, MqttError> {
+    // Build the placeholder values
+    let prefix = prefix.trim_end_matches('/');
+    let name = name.trim_start_matches('/');
+
+    // Expand the placeholders and copy the literal parts in between
+    // Note: The template has been validated when loading the config, so unknown placeholders cannot occur
+    let (mut topic, mut template) = (MqttBuffer::new(), template);
+    while let Some((literal, rest)) = template.split_once('{') {
+        let (placeholder, rest) = rest.split_once('}').ok_or(MqttError::InvalidTopic)?;
+        let value = match placeholder {
+            "prefix" => prefix.as_bytes(),
+            "device" => device,
+            "name" => name.as_bytes(),
+            _ => return Err(MqttError::InvalidTopic),
+        };
+        topic.try_extend(literal.as_bytes()).map_err(|_| MqttError::InvalidTopic)?;
+        topic.try_extend(value).map_err(|_| MqttError::InvalidTopic)?;
+        template = rest;
+    }
+    topic.try_extend(template.as_bytes()).map_err(|_| MqttError::InvalidTopic)?;
+    Ok(topic)
+}
+
+/// Assembles the full topic for the given topic name, or fails if it is not a valid publish topic; see [`topic`]
+///
+/// # Note
+/// Wildcards and null characters are illegal in publish topics, and the server would drop the connection instead of
+/// rejecting the single message.
+pub fn publish_topic<const N: usize>(
+    template: &str,
+    prefix: &str,
+    device: &[u8],
+    name: &str,
+) -> Result<MqttBuffer<N>, MqttError> {
+    let topic = topic(template, prefix, device, name)?;
+    match topic.iter().any(|byte| matches!(byte, b'+' | b'#' | b'\0')) {
+        true => Err(MqttError::InvalidTopic),
+        false => Ok(topic),
+    }
+}
+
+/// Sends an MQTT packet to the given stream, or fails without sending anything if the packet exceeds the buffer size of
+/// `N` bytes
+pub async fn send<const N: usize, W, Packet>(stream: &mut W, packet: Packet, flush: bool) -> Result<(), MqttError>
+where
+    W: Write,
+    Packet: IntoIterator<Item = u8>,
+{
+    // Serialize and send the given packet
+    let packet = MqttBuffer::<N>::try_from_iter(packet).map_err(|_| MqttError::TooLarge)?;
+    stream.write_all(&packet).await.map_err(|_| MqttError::Io)?;
+    if flush {
+        // Flush packet if we are waiting for an immediate answer
+        stream.flush().await.map_err(|_| MqttError::Io)?;
+    }
+    Ok(())
+}
+
+/// A receive buffer that frames MQTT packets from a byte stream with a capacity of `N` bytes
+pub struct PacketBuffer<const N: usize> {
+    /// A buffer to hold read data
+    buf: [u8; N],
+    /// The start of the unconsumed data within the buffer
+    start: usize,
+    /// The end of the unconsumed data within the buffer
+    end: usize,
+    /// The amount of bytes of a discarded packet that still need to be dropped from the incoming data
+    discard: usize,
+}
+impl<const N: usize> PacketBuffer<N> {
+    /// Creates a new, empty packet buffer
+    #[allow(clippy::new_without_default, reason = "buffers are always created explicitly")]
+    pub const fn new() -> Self {
+        Self { buf: [0; N], start: 0, end: 0, discard: 0 }
+    }
+
+    /// Reads from the given stream until a complete packet is available, and decodes it; see [`Self::decode`]
+    pub async fn recv<R, Packet>(&mut self, stream: &mut R) -> Result<Packet, MqttError>
+    where
+        R: Read,
+        Packet: TryFromIterator,
+    {
+        // Read until a complete packet is available
+        loop {
+            if let Some(packet) = self.decode()? {
+                break Ok(packet);
+            }
+
+            // Read some more data
+            match stream.read(self.spare()).await.map_err(|_| MqttError::Io)? {
+                0 => return Err(MqttError::Closed),
+                read => self.commit(read),
+            }
+        }
+    }
+
+    /// Decodes the next packet from the unconsumed data, or returns `None` if the data is truncated
+    ///
+    /// # Note
+    /// The packets are controlled by the server, so invalid packets must not crash the device: A packet that exceeds
+    /// the buffer or the decoder capacity is discarded and reported as [`MqttError::TooLarge`], so the session stays
+    /// usable. A malformed packet fails with [`MqttError::Protocol`].
+    fn decode<Packet>(&mut self) -> Result<Option<Packet>, MqttError>
+    where
+        Packet: TryFromIterator,
+    {
+        // Create a counting iterator over the available bytes
+        let mut consumed = 0;
+        let available = self.buf[self.start..self.end].iter().inspect(|_| consumed += 1).copied();
+
+        // Try to parse the available data, as a previous read may have received more than one packet
+        match Packet::try_from_iter(available) {
+            Ok(packet) => {
+                // Consume bytes by skipping them, and rewind if the buffer has been drained completely
+                self.start += consumed;
+                if self.start == self.end {
+                    (self.start, self.end) = (0, 0);
+                }
+                Ok(Some(packet))
+            }
+            Err(e) => match e.variant {
+                Decoding::Truncated if self.end - self.start < N => Ok(None),
+                Decoding::Truncated | Decoding::Memory => {
+                    // Discard the entire packet, as it can never be decoded
+                    let len = self.frame_len()?.ok_or(MqttError::Protocol)?;
+                    self.skip(len);
+                    Err(MqttError::TooLarge)
+                }
+                Decoding::SpecViolation => Err(MqttError::Protocol),
+            },
+        }
+    }
+
+    /// The total length of the packet at the start of the unconsumed data, or `None` if the fixed header is truncated
+    fn frame_len(&self) -> Result<Option<usize>, MqttError> {
+        // Decode the remaining length, which is encoded as variable-length integer of at most 4 bytes after the type
+        let available = &self.buf[self.start..self.end];
+        let mut remaining = 0;
+        for (index, byte) in available.iter().enumerate().skip(1).take(4) {
+            remaining |= ((byte & 0x7F) as usize) << (7 * (index - 1));
+            if byte & 0x80 == 0 {
+                return Ok(Some(1 + index + remaining));
+            }
+        }
+        match available.len() > 4 {
+            true => Err(MqttError::Protocol),
+            false => Ok(None),
+        }
+    }
+
+    /// Skips the given amount of bytes, and drops the bytes which have not been read yet from the incoming data
+    fn skip(&mut self, len: usize) {
+        let available = self.end - self.start;
+        match len < available {
+            true => self.start += len,
+            false => (self.start, self.end, self.discard) = (0, 0, len - available),
+        }
+    }
+
+    /// The spare capacity to read more data into; see [`Self::commit`]
+    fn spare(&mut self) -> &mut [u8] {
+        // Compact the unconsumed data only if there is no more space left at the end
+        if self.end == self.buf.len() {
+            self.buf.copy_within(self.start..self.end, 0);
+            (self.start, self.end) = (0, self.end - self.start);
+        }
+        &mut self.buf[self.end..]
+    }
+
+    /// Marks the given amount of bytes within the spare capacity as read
+    fn commit(&mut self, len: usize) {
+        // Drop the leading bytes that still belong to a discarded packet
+        let dropped = len.min(self.discard);
+        self.buf.copy_within(self.end + dropped..self.end + len, self.end);
+        self.discard -= dropped;
+        self.end += len - dropped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use mqtt_tiny::{Connack, Publish};
+
+    /// An in-memory stream that serves the incoming data in the given chunks, and records the outgoing data
+    #[derive(Default)]
+    struct MockStream {
+        /// The incoming data, one chunk per read
+        incoming: Vec<&'static [u8]>,
+        /// The amount of reads so far
+        reads: usize,
+        /// The outgoing data
+        outgoing: Vec<u8>,
+        /// The amount of flushes so far
+        flushes: usize,
+    }
+    impl ErrorType for MockStream {
+        type Error = ErrorKind;
+    }
+    impl Read for MockStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Serve the next chunk, or signal a closed stream if all chunks have been served
+            let chunk = self.incoming.get(self.reads).copied().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.reads += 1;
+            Ok(chunk.len())
+        }
+    }
+    impl Write for MockStream {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.outgoing.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    /// Assembles the publish topic for the given prefix and topic name with the default template
+    fn publish(prefix: &str, name: &str) -> Result<Vec<u8>, MqttError> {
+        publish_topic::<64>("{prefix}/{name}", prefix, b"", name).map(|topic| topic.to_vec())
+    }
+
+    #[test]
+    fn topic_join() {
+        assert_eq!(publish("garden", "moisture").unwrap(), b"garden/moisture");
+        assert_eq!(publish("garden/sensors", "moisture").unwrap(), b"garden/sensors/moisture");
+    }
+
+    #[test]
+    fn topic_empty_prefix() {
+        // The separator is part of the template, so it is kept
+        assert_eq!(publish("", "moisture").unwrap(), b"/moisture");
+        assert_eq!(publish("", "/moisture").unwrap(), b"/moisture");
+    }
+
+    #[test]
+    fn topic_trailing_slash() {
+        // Trailing slashes of the prefix and leading slashes of the name are trimmed, so they cannot double up
+        assert_eq!(publish("garden/", "moisture").unwrap(), b"garden/moisture");
+        assert_eq!(publish("garden//", "//moisture").unwrap(), b"garden/moisture");
+
+        // A trailing slash of the name is part of the topic
+        assert_eq!(publish("garden", "moisture/").unwrap(), b"garden/moisture/");
+    }
+
+    #[test]
+    fn topic_template() {
+        let topic = publish_topic::<64>("{prefix}/{device}/{name}", "garden/", b"e6614c31", "moisture").unwrap();
+        assert_eq!(&*topic, b"garden/e6614c31/moisture");
+
+        // Unknown placeholders and topics that exceed the buffer are rejected
+        let result = publish_topic::<64>("{prefix}/{unknown}", "garden", b"", "moisture");
+        assert!(matches!(result, Err(MqttError::InvalidTopic)));
+        let result = publish_topic::<8>("{prefix}/{name}", "garden", b"", "moisture");
+        assert!(matches!(result, Err(MqttError::InvalidTopic)));
+    }
+
+    #[test]
+    fn topic_invalid_characters() {
+        // Wildcards and null characters are rejected for publishing, regardless of where they occur
+        for (prefix, name) in [("garden/+", "moisture"), ("garden/#", "moisture"), ("gar\0den", "moisture")] {
+            assert!(matches!(publish(prefix, name), Err(MqttError::InvalidTopic)), "{prefix:?} {name:?}");
+        }
+        for (prefix, name) in [("garden", "+/moisture"), ("garden", "moisture/#"), ("garden", "moist\0ure")] {
+            assert!(matches!(publish(prefix, name), Err(MqttError::InvalidTopic)), "{prefix:?} {name:?}");
+        }
+
+        // Wildcards are legal for subscriptions
+        let topic = topic::<64>("{prefix}/{name}", "garden", b"", "+/set").unwrap();
+        assert_eq!(&*topic, b"garden/+/set");
+    }
+
+    #[test]
+    fn send_publish() {
+        // Assemble and send a QoS 1 publish packet
+        let mut stream = MockStream::default();
+        let topic = publish_topic::<64>("{prefix}/{name}", "garden/", b"", "/moisture").expect("invalid topic");
+        let publish = Publish::new(topic, b"0.42", false).expect("failed to assemble packet").with_qos(1, 7, false);
+        block_on(send::<64, _, _>(&mut stream, publish, true)).expect("failed to send packet");
+
+        // Header with QoS 1, remaining length, topic, packet ID, and payload
+        let mut expected = vec![0x32, 23, 0, 15];
+        expected.extend_from_slice(b"garden/moisture");
+        expected.extend_from_slice(&[0, 7]);
+        expected.extend_from_slice(b"0.42");
+        assert_eq!(stream.outgoing, expected);
+        assert_eq!(stream.flushes, 1);
+    }
+
+    #[test]
+    fn send_too_large() {
+        // An oversized packet must not be sent partially
+        let mut stream = MockStream::default();
+        let publish = Publish::new(b"garden/moisture", b"0.42", false).expect("failed to assemble packet");
+        let result = block_on(send::<16, _, _>(&mut stream, publish, true));
+        assert!(matches!(result, Err(MqttError::TooLarge)));
+        assert!(stream.outgoing.is_empty());
+        assert_eq!(stream.flushes, 0);
+    }
+
+    #[test]
+    fn recv_truncated_connack() {
+        // The first read only yields the fixed header, so the packet buffer has to read again
+        let mut stream = MockStream { incoming: vec![&[0x20, 0x02], &[0x01, 0x00]], ..Default::default() };
+        let mut packets = PacketBuffer::<64>::new();
+        let connack: Connack = block_on(packets.recv(&mut stream)).expect("failed to receive packet");
+        assert_eq!(connack, Connack::new(true, 0));
+        assert_eq!(stream.reads, 2);
+    }
+
+    #[test]
+    fn recv_closed() {
+        // A stream that closes mid-packet must not yield a packet
+        let mut stream = MockStream { incoming: vec![&[0x20, 0x02, 0x01]], ..Default::default() };
+        let mut packets = PacketBuffer::<64>::new();
+        let result = block_on(packets.recv::<_, Connack>(&mut stream));
+        assert!(matches!(result, Err(MqttError::Closed)));
+    }
+}
@@ -0,0 +1,135 @@
+//! Minimal SNTP client
+
+use crate::debug_println;
+use core::fmt::{Display, Formatter};
+use core::net::{Ipv4Addr, SocketAddrV4};
+use embassy_net::Stack;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::{Duration, Instant, with_timeout};
+
+/// The default NTP port
+const NTP_PORT: u16 = 123;
+/// The maximum time to wait for the NTP server to respond
+const NTP_TIMEOUT: Duration = Duration::from_secs(2);
+/// The SNTP packet size
+const PACKET_SIZE: usize = 48;
+
+/// A wall clock synchronized via SNTP
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    /// The unix time at synchronization in seconds
+    unix_secs: u64,
+    /// The instant of synchronization
+    synced_at: Instant,
+}
+impl WallClock {
+    /// Synchronizes the clock with the given NTP server, or returns `None` if no server is configured or the query fails
+    pub async fn sync(network: Stack<'_>, server: &str) -> Option<Self> {
+        // Parse IPv4 server address, with or without port
+        if server.is_empty() {
+            return None;
+        }
+        let server = match (server.parse::<SocketAddrV4>(), server.parse::<Ipv4Addr>()) {
+            (Ok(address), _) => address,
+            (_, Ok(address)) => SocketAddrV4::new(address, NTP_PORT),
+            _ => {
+                // Log warning and skip synchronization
+                debug_println!("[warn] invalid ntp server address: {}", server);
+                return None;
+            }
+        };
+
+        // Create socket
+        let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 1], [PacketMetadata::EMPTY; 1]);
+        let (mut rx_buf, mut tx_buf) = ([0; PACKET_SIZE], [0; PACKET_SIZE]);
+        let mut socket = UdpSocket::new(network, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+        socket.bind(0).expect("failed to bind ntp socket");
+
+        // Send request and await response
+        // Note: The first byte encodes leap indicator 0, version 4 and mode 3 (client)
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = 0b00_100_011;
+        let query = async {
+            socket.send_to(&packet, server).await.ok()?;
+            socket.recv_from(&mut packet).await.ok()
+        };
+        let Ok(Some((PACKET_SIZE, _))) = with_timeout(NTP_TIMEOUT, query).await else {
+            // Log warning and skip synchronization
+            debug_println!("[warn] ntp query failed");
+            return None;
+        };
+
+        // Validate response
+        // Note: Mode 4 is a server response, and stratum 0 is a kiss-of-death packet
+        let (mode, stratum) = (packet[0] & 0b111, packet[1]);
+        if mode != 4 || stratum == 0 {
+            // Log warning and skip synchronization
+            debug_println!("[warn] invalid ntp response: mode {}, stratum {}", mode, stratum);
+            return None;
+        }
+
+        // Convert the transmit timestamp to unix time
+        // Note: Timestamps with the highest bit unset belong to the next NTP era which starts in 2036
+        let ntp_secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+        let ntp_secs = if ntp_secs & 0x8000_0000 == 0 { ntp_secs + (1 << 32) } else { ntp_secs };
+        let unix_secs = ntp_secs - 2_208_988_800;
+        Some(Self::from_unix_secs(unix_secs))
+    }
+
+    /// Creates a clock that is synchronized to the given unix time now
+    pub(crate) fn from_unix_secs(unix_secs: u64) -> Self {
+        Self { unix_secs, synced_at: Instant::now() }
+    }
+
+    /// The current unix time in seconds
+    pub(crate) fn unix_secs(&self) -> u64 {
+        self.unix_secs + self.synced_at.elapsed().as_secs()
+    }
+
+    /// The current time
+    pub fn now(&self) -> Timestamp {
+        Timestamp(self.unix_secs())
+    }
+}
+
+/// A unix timestamp that displays as ISO 8601 UTC date and time
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp(u64);
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        // Split into civil date and time of day
+        let (days, secs) = (self.0 / 86_400, self.0 % 86_400);
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// Converts the days since the unix epoch into a civil `(year, month, day)` date
+///
+/// # Note
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+pub(crate) const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// Converts a civil date into the days since the unix epoch
+///
+/// # Note
+/// See <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>. Dates before the epoch are not
+/// supported.
+pub(crate) const fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
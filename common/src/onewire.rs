@@ -0,0 +1,173 @@
+//! Bit-banged one-wire DS18B20 soil temperature sensor handling
+
+use core::fmt::{Display, Formatter};
+use embassy_rp::Peri;
+use embassy_rp::gpio::{Flex, Pin, Pull};
+use embassy_time::{Duration, Instant, Timer, block_for};
+
+/// The ROM command to address the only device on the bus
+const SKIP_ROM: u8 = 0xCC;
+/// The function command to start a temperature conversion
+const CONVERT_T: u8 = 0x44;
+/// The function command to read the scratchpad
+const READ_SCRATCHPAD: u8 = 0xBE;
+/// The maximum conversion duration with 12 bit resolution
+const CONVERT_DURATION: Duration = Duration::from_millis(750);
+/// The conversion polling interval
+const CONVERT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A soil temperature read error
+#[derive(Debug)]
+pub enum OneWireError {
+    /// No device answered the reset pulse
+    Missing,
+    /// The temperature conversion did not complete in time
+    Timeout,
+    /// The scratchpad is corrupt
+    Checksum,
+}
+impl Display for OneWireError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "no soil temperature sensor connected"),
+            Self::Timeout => write!(f, "timed out waiting for soil temperature conversion"),
+            Self::Checksum => write!(f, "invalid soil temperature sensor checksum"),
+        }
+    }
+}
+
+/// A single DS18B20 soil temperature sensor on a dedicated one-wire bus
+///
+/// # Note
+/// The bus needs an external pull-up resistor (usually 4.7kΩ); the internal pull-up is enabled as well, but is too weak
+/// for longer cables. Parasite power is not supported.
+pub struct SoilThermometer {
+    /// The one-wire data pin
+    pin: Flex<'static>,
+}
+impl SoilThermometer {
+    /// Creates a new soil thermometer instance on the given data pin
+    pub fn new<P>(pin: Peri<'static, P>) -> Self
+    where
+        P: Pin + 'static,
+    {
+        // Release the bus
+        let mut pin = Flex::new(pin);
+        pin.set_pull(Pull::Up);
+        pin.set_low();
+        pin.set_as_input();
+        Self { pin }
+    }
+
+    /// Reads the soil temperature in degrees Celsius
+    pub async fn read(&mut self) -> Result<f64, OneWireError> {
+        // Start the conversion and poll until it completes
+        // Note: During conversion, the device answers read slots with zero
+        self.reset()?;
+        self.write(&[SKIP_ROM, CONVERT_T]);
+        let deadline = Instant::now() + CONVERT_DURATION + CONVERT_POLL_INTERVAL;
+        while !self.read_bit() {
+            if Instant::now() > deadline {
+                return Err(OneWireError::Timeout);
+            }
+            Timer::after(CONVERT_POLL_INTERVAL).await;
+        }
+
+        // Read and validate the scratchpad
+        let mut scratchpad = [0; 9];
+        self.reset()?;
+        self.write(&[SKIP_ROM, READ_SCRATCHPAD]);
+        for byte in &mut scratchpad {
+            *byte = self.read_byte();
+        }
+        if crc8(&scratchpad[..8]) != scratchpad[8] {
+            // Reject corrupt responses
+            return Err(OneWireError::Checksum);
+        }
+
+        // Convert the raw value
+        // Note: The temperature is a signed 12 bit value with 4 fractional bits
+        let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+        Ok(raw as f64 / 16.0)
+    }
+
+    /// Sends a reset pulse, and fails if no device answers with a presence pulse
+    fn reset(&mut self) -> Result<(), OneWireError> {
+        // Pull the bus low for the reset pulse, and sample the presence pulse
+        let present = critical_section::with(|_| {
+            self.pull_low(Duration::from_micros(480));
+            self.release(Duration::from_micros(70));
+            self.pin.is_low()
+        });
+
+        // Wait for the presence pulse to finish
+        block_for(Duration::from_micros(410));
+        match present {
+            true => Ok(()),
+            false => Err(OneWireError::Missing),
+        }
+    }
+
+    /// Writes the given bytes LSB-first
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            for bit in 0..8 {
+                // Write a single time slot
+                // Note: Ones are a short low pulse, zeroes are a long low pulse
+                critical_section::with(|_| match (byte >> bit) & 1 {
+                    1 => {
+                        self.pull_low(Duration::from_micros(6));
+                        self.release(Duration::from_micros(64));
+                    }
+                    _ => {
+                        self.pull_low(Duration::from_micros(60));
+                        self.release(Duration::from_micros(10));
+                    }
+                });
+            }
+        }
+    }
+
+    /// Reads a byte LSB-first
+    fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, bit| byte | ((self.read_bit() as u8) << bit))
+    }
+
+    /// Reads a single bit
+    fn read_bit(&mut self) -> bool {
+        // Initiate the time slot and sample the bus before the device releases it
+        let bit = critical_section::with(|_| {
+            self.pull_low(Duration::from_micros(6));
+            self.release(Duration::from_micros(9));
+            self.pin.is_high()
+        });
+
+        // Wait for the time slot to finish
+        block_for(Duration::from_micros(55));
+        bit
+    }
+
+    /// Pulls the bus low for the given duration
+    fn pull_low(&mut self, duration: Duration) {
+        self.pin.set_as_output();
+        block_for(duration);
+    }
+
+    /// Releases the bus and waits for the given duration
+    fn release(&mut self, duration: Duration) {
+        self.pin.set_as_input();
+        block_for(duration);
+    }
+}
+
+/// Computes the Maxim one-wire CRC-8 checksum (polynomial `0x31` reflected, initial value `0x00`)
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 { (crc >> 1) ^ 0x8C } else { crc >> 1 };
+        }
+    }
+    crc
+}
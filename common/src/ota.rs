@@ -0,0 +1,81 @@
+//! Over-the-air firmware update check
+//!
+//! # Note
+//! This only detects and announces available updates; downloading and staging the firmware image is not implemented yet.
+
+use crate::debug_println;
+use core::fmt::{Display, Formatter};
+
+/// A semantic firmware version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    /// The major version
+    pub major: u16,
+    /// The minor version
+    pub minor: u16,
+    /// The patch version
+    pub patch: u16,
+}
+impl Version {
+    /// Parses a `major.minor.patch` version with optional `v` prefix, and ignores any pre-release or build suffix
+    pub fn parse(version: &str) -> Option<Self> {
+        // Strip prefix and suffixes
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version.split(['-', '+']).next()?;
+
+        // Parse the components
+        let mut components = version.split('.').map(str::parse::<u16>);
+        let (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) =
+            (components.next(), components.next(), components.next(), components.next())
+        else {
+            return None;
+        };
+        Some(Self { major, minor, patch })
+    }
+}
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// An over-the-air update client
+#[derive(Debug, Clone, Copy)]
+pub struct OtaClient {
+    /// The version of the running firmware
+    current: Version,
+    /// The newest available version, if it is newer than the running firmware
+    available: Option<Version>,
+}
+impl OtaClient {
+    /// The topic where the latest firmware version is announced as retained message
+    pub const TOPIC: &str = "ota/available";
+
+    /// Creates a new OTA client for the running firmware with the given version
+    pub fn new(current: Version) -> Self {
+        Self { current, available: None }
+    }
+
+    /// Checks an announcement from [`Self::TOPIC`], and returns whether it announces a newer version
+    pub fn check(&mut self, announcement: &[u8]) -> bool {
+        // Parse the announced version
+        let Some(version) = str::from_utf8(announcement).ok().and_then(Version::parse) else {
+            // Log warning and ignore
+            debug_println!("[warn] invalid ota announcement");
+            return false;
+        };
+
+        // Record the version if it is newer
+        let newer = version > self.current && self.available.is_none_or(|available| version > available);
+        if newer {
+            self.available = Some(version);
+        }
+        newer
+    }
+
+    /// The newest available version, if it is newer than the running firmware
+    pub const fn available(&self) -> Option<Version> {
+        self.available
+    }
+}
@@ -0,0 +1,226 @@
+//! Panic handler and after-panic signalizer
+
+use crate::debug::{StatusLedMode, StatusLedSession};
+use crate::debug_println;
+use crate::lifecycle::Lifecycle;
+use core::ops::Deref;
+use core::panic::PanicInfo;
+use cortex_m::asm;
+use cortex_m::peripheral::SCB;
+use embassy_rp::pac::WATCHDOG;
+use embassy_rp::pac::common::{RW, Reg};
+use embassy_time::{Duration, Timer};
+
+/// The truncated location of the last panic that persists across resets
+#[derive(Debug, Clone, Copy)]
+pub struct LastPanic {
+    /// The `file:line` location
+    buf: [u8; Self::CAPACITY],
+    /// The location length
+    len: usize,
+}
+impl LastPanic {
+    /// The location capacity (watchdog scratch registers 4 to 7)
+    const CAPACITY: usize = 16;
+    /// The scratch checksum XOR constant
+    const CHECKSUM_XOR: u32 = 0x5C0F13A7;
+
+    /// Captures the location of the given panic
+    fn new(info: &PanicInfo) -> Self {
+        use core::fmt::Write;
+
+        // Format location
+        // Note: We strip the directory as the file name is usually sufficient, and truncate what does not fit
+        let mut this = Self { buf: [0; Self::CAPACITY], len: 0 };
+        if let Some(location) = info.location() {
+            let file = location.file().rsplit(['/', '\\']).next().unwrap_or_default();
+            let _ = write!(&mut this, "{}:{}", file, location.line());
+        }
+        this
+    }
+
+    /// Persists the panic location
+    ///
+    /// # Note
+    /// The boot ROM also uses the scratch registers 4 to 7 for reboot parameters, but only if scratch register 4 holds a
+    /// magic value that is not valid ASCII and can thus never be produced by a panic location.
+    fn store(&self) {
+        // Store location words and checksum
+        // Note: The checksum is folded into the lower half, as the upper half is shared with the sleep probe
+        let mut checksum = Self::CHECKSUM_XOR;
+        for (register, word) in Self::registers().into_iter().zip(self.buf.chunks_exact(4)) {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            register.write_value(word);
+            checksum ^= word;
+        }
+        let shared = WATCHDOG.scratch3().read() & 0xFFFF_0000;
+        WATCHDOG.scratch3().write_value(shared | Self::fold(checksum) as u32);
+    }
+
+    /// Loads the last-persisted panic location, if any
+    pub fn load() -> Option<Self> {
+        // Load location words and validate checksum
+        let mut this = Self { buf: [0; Self::CAPACITY], len: 0 };
+        let mut checksum = Self::CHECKSUM_XOR;
+        for (register, word) in Self::registers().into_iter().zip(this.buf.chunks_exact_mut(4)) {
+            let value = register.read();
+            word.copy_from_slice(&value.to_le_bytes());
+            checksum ^= value;
+        }
+        let true = WATCHDOG.scratch3().read() as u16 == Self::fold(checksum) else {
+            // No location has been stored
+            return None;
+        };
+
+        // Compute length
+        // Note: A location always starts with the file name, so an empty location can only be a cleared one that has been
+        //  revalidated by a write to the shared scratch registers 6 or 7 (see `WakeCounter` and `ReadingAverage`)
+        this.len = this.buf.iter().position(|byte| *byte == 0).unwrap_or(Self::CAPACITY);
+        (this.len > 0).then_some(this)
+    }
+
+    /// Clears the last-persisted panic location
+    pub fn clear() {
+        // Invalidate location and checksum
+        // Note: The location is emptied as well, as the wake counter and the reading average may reuse the scratch
+        //  registers 6 and 7 afterwards
+        WATCHDOG.scratch4().write_value(0);
+        let checksum = WATCHDOG.scratch3().read();
+        WATCHDOG.scratch3().write_value(checksum ^ 0x0000_FFFF);
+    }
+
+    /// Folds the given checksum into 16 bits
+    const fn fold(checksum: u32) -> u16 {
+        (checksum ^ (checksum >> 16)) as u16
+    }
+
+    /// The scratch registers that hold the location
+    fn registers() -> [Reg<u32, RW>; 4] {
+        [WATCHDOG.scratch4(), WATCHDOG.scratch5(), WATCHDOG.scratch6(), WATCHDOG.scratch7()]
+    }
+}
+impl Deref for LastPanic {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf[..self.len]
+    }
+}
+impl core::fmt::Write for LastPanic {
+    fn write_str(&mut self, str_: &str) -> core::fmt::Result {
+        // Copy as much data as fits and increment length
+        // Note: We truncate silently, as a partial location is still better than none
+        let remaining = &mut self.buf[self.len..];
+        let len = str_.len().min(remaining.len());
+        remaining[..len].copy_from_slice(&str_.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// A counter of consecutive panics that persists across resets
+///
+/// # Note
+/// The count is only 4 bit wide and saturates, as it shares its scratch register with the reading sequence.
+#[derive(Debug, Clone, Copy)]
+pub struct PanicCounter;
+impl PanicCounter {
+    /// The maximum panic count (4 bit)
+    const COUNT_MAX: u8 = 0x0F;
+    /// The scratch checksum XOR constant (4 bit)
+    const CHECKSUM_XOR: u8 = 0x0B;
+
+    /// Persists the given panic count
+    fn store(count: u8) {
+        // Note: The upper three bytes of the scratch register hold the reading sequence, so the count and its checksum
+        //  are packed into the lowest byte
+        let count = count.min(Self::COUNT_MAX);
+        let checksum = count ^ Self::CHECKSUM_XOR;
+        let shared = WATCHDOG.scratch2().read() & 0xFFFF_FF00;
+        WATCHDOG.scratch2().write_value(shared | ((checksum as u32) << 4) | count as u32);
+    }
+
+    /// Loads the last-persisted panic count, or zero if no count has been stored
+    fn load() -> u8 {
+        let value = WATCHDOG.scratch2().read();
+        let (count, checksum) = (value as u8 & 0x0F, (value >> 4) as u8 & 0x0F);
+        match count == (checksum ^ Self::CHECKSUM_XOR) {
+            true => count,
+            false => 0,
+        }
+    }
+
+    /// Resets the panic count after a clean cycle
+    pub fn reset() {
+        Self::store(0);
+    }
+}
+
+/// Graceful after-panic handler to signalize the panic to the user, and to run the given best-effort crash notice while
+/// signaling
+///
+/// # Note
+/// This handler performs a reset directly, unless the device has panicked too often in a row; in this case, it signals
+/// the panic loop to the user and returns the duration for which the device should be held in low-power sleep. The
+/// crash notice is skipped in this case, as it may be the cause of the panic loop.
+pub async fn after_panic<F>(led: &StatusLedSession, notice: F) -> Duration
+where
+    F: Future<Output = ()>,
+{
+    /// The post-panic signal duration after a first, likely transient panic
+    const TRANSIENT_DURATION: Duration = Duration::from_secs(2);
+    /// The post-panic signal duration after repeated panics, which hint at a persistent error (~2 SOS patterns)
+    const PERSISTENT_DURATION: Duration = Duration::from_secs(9);
+    /// The amount of consecutive panics after which the device is held in low-power sleep
+    const PANIC_LIMIT: u8 = 3;
+    /// The low-power sleep duration after too many consecutive panics
+    const HOLD_DURATION: Duration = Duration::from_secs(6 * 60 * 60);
+
+    // After this handler, we want to enter the normal cycle again
+    Lifecycle::store(Lifecycle::LIGHTSLEEP);
+    debug_println!("[info] executing after-panic task");
+
+    // Count the panic
+    let panics = PanicCounter::load().saturating_add(1);
+    PanicCounter::store(panics);
+    if panics >= PANIC_LIMIT {
+        // Signal the panic loop and hold the device to save energy
+        debug_println!("[warn] panicked {} times in a row; holding device", panics);
+        led.set(StatusLedMode::Sos);
+        Timer::after(PERSISTENT_DURATION).await;
+        return HOLD_DURATION;
+    }
+
+    // Signal the panic, and tell a first, likely transient panic apart from a persistent error
+    let (mode, duration) = match panics {
+        1 => (StatusLedMode::Blink, TRANSIENT_DURATION),
+        _ => (StatusLedMode::Sos, PERSISTENT_DURATION),
+    };
+    debug_println!("[info] signaling panic {} of {} before holding device", panics, PANIC_LIMIT);
+    led.set(mode);
+    let signal = Timer::after(duration);
+
+    // Run the crash notice while signaling
+    // Note: The panic has already been counted, so a notice that panics again still ends up in the hold sleep
+    notice.await;
+    signal.await;
+
+    // Perform reset
+    debug_println!("[info] performing graceful post-panic reset");
+    SCB::sys_reset();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // Disable all interrupts
+    cortex_m::interrupt::disable();
+    debug_println!("{}", info);
+
+    // Persist the panic location for later diagnosis
+    LastPanic::new(info).store();
+
+    // Crash and wait until the watchdog kills us
+    // Note: If the watchdog is not yet running, we crash so early that a normal reset wouldn't help either.
+    asm::bkpt();
+    asm::udf();
+}
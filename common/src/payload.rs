@@ -0,0 +1,288 @@
+//! Structured payload encoding, independent of the network stack, so it can be tested on the host
+
+use crate::mqtt_codec::{MqttBuffer, MqttError};
+use core::fmt::{Display, Formatter};
+
+/// The MQTT payload format for sensor readouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttFormat {
+    /// Plain text values, one topic per value
+    Text,
+    /// A single JSON object
+    Json,
+    /// A single CBOR map
+    Cbor,
+}
+impl MqttFormat {
+    /// The format for payloads that are always structured, which is JSON unless CBOR has been selected explicitly
+    pub const fn structured(self) -> Self {
+        match self {
+            Self::Text => Self::Json,
+            format => format,
+        }
+    }
+
+    /// Parses the payload format from its config name
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+}
+impl Display for MqttFormat {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+            Self::Cbor => write!(f, "cbor"),
+        }
+    }
+}
+
+/// An encoder for a flat map of named values
+///
+/// # Note
+/// CBOR maps are encoded with indefinite length, so fields can be appended without knowing their count upfront. If the
+/// payload exceeds the buffer, the overflow is recorded and reported by [`Self::finish`], so the fields can be chained.
+pub struct Payload<const N: usize> {
+    /// The payload format
+    format: MqttFormat,
+    /// The encoded payload
+    buf: MqttBuffer<N>,
+    /// Whether a field has been written already
+    has_fields: bool,
+    /// Whether the payload has exceeded the buffer
+    overflow: bool,
+}
+impl<const N: usize> Payload<N> {
+    /// Starts a new payload in the given format
+    ///
+    /// # Panics
+    /// This function panics if the format is [`MqttFormat::Text`], as text values are published individually.
+    pub fn new(format: MqttFormat) -> Self {
+        let mut this = Self { format, buf: MqttBuffer::new(), has_fields: false, overflow: false };
+        match format {
+            MqttFormat::Json => this.extend(b"{"),
+            MqttFormat::Cbor => this.extend(&[0xBF]),
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        this
+    }
+
+    /// Appends a floating point field
+    pub fn float(&mut self, key: &str, value: f64) -> &mut Self {
+        self.key(key);
+        match self.format {
+            // Note: JSON has no representation for NaN or infinity
+            MqttFormat::Json if value.is_finite() => self.display(value),
+            MqttFormat::Json => self.extend(b"null"),
+            MqttFormat::Cbor => {
+                // Note: Major type 7 with additional information 27 is an IEEE 754 double
+                self.extend(&[0xFB]);
+                self.extend(&value.to_be_bytes());
+            }
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        self
+    }
+
+    /// Appends an unsigned integer field
+    pub fn unsigned(&mut self, key: &str, value: u64) -> &mut Self {
+        self.key(key);
+        match self.format {
+            MqttFormat::Json => self.display(value),
+            MqttFormat::Cbor => self.head(0, value),
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        self
+    }
+
+    /// Appends a signed integer field
+    pub fn signed(&mut self, key: &str, value: i64) -> &mut Self {
+        self.key(key);
+        match self.format {
+            MqttFormat::Json => self.display(value),
+            // Note: Negative integers are encoded as major type 1 with the argument `-1 - value`
+            MqttFormat::Cbor if value < 0 => self.head(1, !value as u64),
+            MqttFormat::Cbor => self.head(0, value as u64),
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        self
+    }
+
+    /// Appends a string field
+    ///
+    /// # Note
+    /// The value is not escaped, so it must be a plain identifier.
+    pub fn text(&mut self, key: &str, value: &str) -> &mut Self {
+        self.key(key);
+        match self.format {
+            MqttFormat::Json => self.display(format_args!("\"{value}\"")),
+            MqttFormat::Cbor => {
+                self.head(3, value.len() as u64);
+                self.extend(value.as_bytes());
+            }
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        self
+    }
+
+    /// Finishes the payload, or fails with [`MqttError::TooLarge`] if the payload exceeds the buffer
+    pub fn finish(mut self) -> Result<MqttBuffer<N>, MqttError> {
+        match self.format {
+            MqttFormat::Json => self.extend(b"}"),
+            MqttFormat::Cbor => self.extend(&[0xFF]),
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+        match self.overflow {
+            true => Err(MqttError::TooLarge),
+            false => Ok(self.buf),
+        }
+    }
+
+    /// Appends a field key
+    fn key(&mut self, key: &str) {
+        // Write separator if appropriate
+        if self.has_fields && self.format == MqttFormat::Json {
+            self.extend(b",");
+        }
+        self.has_fields = true;
+
+        // Write key
+        match self.format {
+            // Note: Keys are static identifiers that never need escaping
+            MqttFormat::Json => {
+                self.display(format_args!("\"{key}\""));
+                self.extend(b":");
+            }
+            MqttFormat::Cbor => {
+                self.head(3, key.len() as u64);
+                self.extend(key.as_bytes());
+            }
+            MqttFormat::Text => unreachable!("text values are not encoded as payload"),
+        }
+    }
+
+    /// Appends a CBOR data item head with the given major type and argument in its shortest form
+    fn head(&mut self, major: u8, argument: u64) {
+        let (info, len) = match argument {
+            0..24 => (argument as u8, 0),
+            24..0x100 => (24, 1),
+            0x100..0x1_0000 => (25, 2),
+            0x1_0000..0x1_0000_0000 => (26, 4),
+            _ => (27, 8),
+        };
+        self.extend(&[major << 5 | info]);
+        self.extend(&argument.to_be_bytes()[8 - len..]);
+    }
+
+    /// Appends the given value formatted as string
+    fn display<T>(&mut self, value: T)
+    where
+        T: Display,
+    {
+        use core::fmt::Write;
+        self.overflow |= write!(&mut self.buf, "{value}").is_err();
+    }
+
+    /// Appends the given bytes
+    fn extend(&mut self, bytes: &[u8]) {
+        self.overflow |= self.buf.try_extend(bytes).is_err();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a single unsigned CBOR field with the key `a`, and returns the encoded value
+    fn cbor_unsigned(value: u64) -> Vec<u8> {
+        let mut payload = Payload::<32>::new(MqttFormat::Cbor);
+        payload.unsigned("a", value);
+        let payload = payload.finish().unwrap();
+        payload[3..payload.len() - 1].to_vec()
+    }
+
+    /// Encodes a single signed CBOR field with the key `a`, and returns the encoded value
+    fn cbor_signed(value: i64) -> Vec<u8> {
+        let mut payload = Payload::<32>::new(MqttFormat::Cbor);
+        payload.signed("a", value);
+        let payload = payload.finish().unwrap();
+        payload[3..payload.len() - 1].to_vec()
+    }
+
+    #[test]
+    fn json_fields() {
+        let mut payload = Payload::<128>::new(MqttFormat::Json);
+        payload.float("voltage", 1.5).unsigned("seq", 7).signed("rssi", -60).text("reset", "watchdog");
+        let expected = br#"{"voltage":1.5,"seq":7,"rssi":-60,"reset":"watchdog"}"#;
+        assert_eq!(&*payload.finish().unwrap(), expected);
+    }
+
+    #[test]
+    fn json_empty_and_non_finite() {
+        assert_eq!(&*Payload::<8>::new(MqttFormat::Json).finish().unwrap(), b"{}");
+
+        // JSON has no representation for NaN or infinity
+        let mut payload = Payload::<64>::new(MqttFormat::Json);
+        payload.float("nan", f64::NAN).float("inf", f64::INFINITY);
+        assert_eq!(&*payload.finish().unwrap(), br#"{"nan":null,"inf":null}"#);
+    }
+
+    #[test]
+    fn cbor_fields() {
+        let mut payload = Payload::<64>::new(MqttFormat::Cbor);
+        payload.float("v", 1.5).text("r", "wd");
+        let expected = [
+            0xBF, // indefinite-length map
+            0x61, b'v', 0xFB, 0x3F, 0xF8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // "v": 1.5
+            0x61, b'r', 0x62, b'w', b'd', // "r": "wd"
+            0xFF, // break
+        ];
+        assert_eq!(&*payload.finish().unwrap(), expected);
+        assert_eq!(&*Payload::<8>::new(MqttFormat::Cbor).finish().unwrap(), [0xBF, 0xFF]);
+    }
+
+    #[test]
+    fn cbor_heads() {
+        assert_eq!(cbor_unsigned(0), [0x00]);
+        assert_eq!(cbor_unsigned(23), [0x17]);
+        assert_eq!(cbor_unsigned(24), [0x18, 0x18]);
+        assert_eq!(cbor_unsigned(255), [0x18, 0xFF]);
+        assert_eq!(cbor_unsigned(256), [0x19, 0x01, 0x00]);
+        assert_eq!(cbor_unsigned(65535), [0x19, 0xFF, 0xFF]);
+        assert_eq!(cbor_unsigned(65536), [0x1A, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(cbor_unsigned(1 << 32), [0x1B, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn cbor_negative() {
+        // Negative integers are encoded with the argument `-1 - value`
+        assert_eq!(cbor_signed(0), [0x00]);
+        assert_eq!(cbor_signed(-1), [0x20]);
+        assert_eq!(cbor_signed(-24), [0x37]);
+        assert_eq!(cbor_signed(-25), [0x38, 0x18]);
+        assert_eq!(cbor_signed(-500), [0x39, 0x01, 0xF3]);
+        assert_eq!(cbor_signed(i64::MIN), [0x3B, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn overflow() {
+        // An overflowing field is reported, even if the closing bytes still fit
+        let mut payload = Payload::<12>::new(MqttFormat::Json);
+        payload.text("reset", "watchdog");
+        assert!(matches!(payload.finish(), Err(MqttError::TooLarge)));
+
+        let mut payload = Payload::<8>::new(MqttFormat::Cbor);
+        payload.unsigned("seq", 1).float("v", 1.5);
+        assert!(matches!(payload.finish(), Err(MqttError::TooLarge)));
+
+        // A payload that fits exactly is fine
+        let mut payload = Payload::<9>::new(MqttFormat::Json);
+        payload.unsigned("seq", 1);
+        assert_eq!(&*payload.finish().unwrap(), br#"{"seq":1}"#);
+    }
+}
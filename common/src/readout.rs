@@ -0,0 +1,142 @@
+//! Sensor readout values and the ADC conversions, independent of the ADC driver, so they can be tested on the host
+
+use core::fmt::{Display, Formatter};
+
+/// A sensor readout result
+pub struct SensorReadout {
+    /// The sensor value
+    pub sensor: f64,
+    /// The temperature value in the requested unit, if the temperature channel is sampled
+    pub temperature: Option<f64>,
+    /// The battery (`VSYS`) voltage
+    pub battery: f64,
+    /// The averaged raw ADC counts of the sensor
+    pub sensor_raw: u16,
+    /// The averaged raw ADC counts of the temperature sensor, if the temperature channel is sampled
+    pub temperature_raw: Option<u16>,
+    /// Whether the sensor voltage is still implausible after a retry, which hints at a loose or shorted probe
+    pub suspect: bool,
+    /// The sensor sample statistics, if requested
+    pub stats: Option<SensorStats>,
+}
+impl SensorReadout {
+    /// Converts a raw ADC value with the given full-scale value and reference voltage into a voltage
+    pub fn voltage(raw: f64, full_scale: f64, vref: f64) -> f64 {
+        (raw * vref) / full_scale
+    }
+
+    /// Converts a raw ADC temperature sensor value with the given full-scale value and reference voltage into the given
+    /// temperature unit
+    pub fn temperature(raw: f64, full_scale: f64, vref: f64, unit: TemperatureUnit) -> f64 {
+        // Note: According to chapter 4.9.5. (RP2040) and 12.4.6. (RP2350) Temperature Sensor in the datasheets
+        unit.convert(27.0 - (Self::voltage(raw, full_scale, vref) - 0.706) / 0.001721)
+    }
+
+    /// Converts a raw ADC battery value with the given full-scale value and reference voltage into the `VSYS` voltage
+    pub fn battery(raw: f64, full_scale: f64, vref: f64) -> f64 {
+        // Note: `VSYS` is connected to the ADC via a 1:3 voltage divider
+        Self::voltage(raw, full_scale, vref) * 3.0
+    }
+}
+
+/// The statistics of the sensor samples behind a readout, in volts
+///
+/// # Note
+/// A high standard deviation indicates electrical noise or a bad probe contact.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorStats {
+    /// The lowest sensor sample
+    pub min: f64,
+    /// The highest sensor sample
+    pub max: f64,
+    /// The standard deviation of the sensor samples
+    pub stddev: f64,
+}
+
+/// The unit for published temperatures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    /// Degrees Celsius
+    Celsius,
+    /// Degrees Fahrenheit
+    Fahrenheit,
+    /// Kelvin
+    Kelvin,
+}
+impl TemperatureUnit {
+    /// Parses the unit from its config name
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "C" => Some(Self::Celsius),
+            "F" => Some(Self::Fahrenheit),
+            "K" => Some(Self::Kelvin),
+            _ => None,
+        }
+    }
+
+    /// Converts the given temperature in degrees Celsius into this unit
+    pub fn convert(self, celsius: f64) -> f64 {
+        match self {
+            Self::Celsius => celsius,
+            Self::Fahrenheit => celsius * 1.8 + 32.0,
+            Self::Kelvin => celsius + 273.15,
+        }
+    }
+}
+impl Display for TemperatureUnit {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Celsius => write!(f, "C"),
+            Self::Fahrenheit => write!(f, "F"),
+            Self::Kelvin => write!(f, "K"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 12-bit full-scale value of the raw ADC counts
+    const FULL_SCALE_12BIT: f64 = 4096.0;
+    /// The 16-bit full-scale value of ADC counts that have been scaled to 16 bit
+    const FULL_SCALE_16BIT: f64 = 65536.0;
+    /// The nominal reference voltage
+    const VREF: f64 = 3.3;
+
+    /// Asserts that the given values are equal within a small tolerance for the float arithmetic
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-4, "{value} != {expected}");
+    }
+
+    #[test]
+    fn voltage_12bit() {
+        assert_close(SensorReadout::voltage(0.0, FULL_SCALE_12BIT, VREF), 0.0);
+        assert_close(SensorReadout::voltage(1000.0, FULL_SCALE_12BIT, VREF), 0.805664);
+        assert_close(SensorReadout::voltage(2048.0, FULL_SCALE_12BIT, VREF), 1.65);
+        assert_close(SensorReadout::voltage(4096.0, FULL_SCALE_12BIT, VREF), 3.3);
+    }
+
+    #[test]
+    fn voltage_16bit() {
+        assert_close(SensorReadout::voltage(0.0, FULL_SCALE_16BIT, VREF), 0.0);
+        assert_close(SensorReadout::voltage(16000.0, FULL_SCALE_16BIT, VREF), 0.805664);
+        assert_close(SensorReadout::voltage(32768.0, FULL_SCALE_16BIT, VREF), 1.65);
+        assert_close(SensorReadout::voltage(65536.0, FULL_SCALE_16BIT, VREF), 3.3);
+    }
+
+    #[test]
+    fn temperature_12bit() {
+        let celsius = |raw| SensorReadout::temperature(raw, FULL_SCALE_12BIT, VREF, TemperatureUnit::Celsius);
+        assert_close(celsius(876.0), 27.138455);
+        assert_close(celsius(1000.0), -30.910553);
+    }
+
+    #[test]
+    fn temperature_16bit() {
+        // The same input voltages as for the 12-bit counts must yield the same temperatures
+        let celsius = |raw| SensorReadout::temperature(raw, FULL_SCALE_16BIT, VREF, TemperatureUnit::Celsius);
+        assert_close(celsius(14016.0), 27.138455);
+        assert_close(celsius(16000.0), -30.910553);
+    }
+}
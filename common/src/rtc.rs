@@ -0,0 +1,174 @@
+//! External DS3231 real-time clock handling
+
+use crate::Irqs;
+use crate::ntp::{WallClock, civil_from_days, days_from_civil};
+use core::fmt::{Display, Formatter};
+use embassy_rp::Peri;
+use embassy_rp::i2c::{AbortReason, Async, Config, Error, I2c, Instance, InterruptHandler, SclPin, SdaPin};
+use embassy_rp::interrupt::typelevel::Binding;
+use embassy_time::Duration;
+
+/// The fixed DS3231 I2C address
+const DS3231_ADDRESS: u8 = 0x68;
+/// The first timekeeping register (seconds)
+const DS3231_TIME: u8 = 0x00;
+/// The status register
+const DS3231_STATUS: u8 = 0x0F;
+/// The oscillator-stop flag in the status register, which is set if the time has been lost
+const DS3231_OSF: u8 = 0x80;
+/// The 12-hour mode flag in the hours register
+const DS3231_12H: u8 = 0x40;
+/// The century flag in the month register
+const DS3231_CENTURY: u8 = 0x80;
+
+/// An external RTC error
+#[derive(Debug)]
+pub enum RtcError {
+    /// No RTC responds on the bus
+    Missing,
+    /// The I2C transfer failed
+    I2c(Error),
+    /// The RTC has lost its time (e.g. due to a drained backup battery) or has never been set
+    Invalid,
+}
+impl Display for RtcError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "no external rtc connected"),
+            Self::I2c(error) => write!(f, "failed to communicate with external rtc: {error:?}"),
+            Self::Invalid => write!(f, "external rtc has lost its time"),
+        }
+    }
+}
+
+/// The external DS3231 real-time clock
+///
+/// # Note
+/// The RTC shares the I2C bus with the air sensor, and always runs in 24-hour mode on UTC.
+pub struct Ds3231<'d, T>
+where
+    T: Instance + 'd,
+{
+    /// I2C driver
+    i2c: I2c<'d, T, Async>,
+}
+impl<'d, T> Ds3231<'d, T>
+where
+    T: Instance + 'd,
+{
+    /// Creates a new RTC instance on the given I2C pins
+    pub fn new<C, D>(i2c: Peri<'d, T>, irqs: Irqs, scl: Peri<'d, C>, sda: Peri<'d, D>) -> Self
+    where
+        Irqs: Binding<T::Interrupt, InterruptHandler<T>>,
+        C: SclPin<T>,
+        D: SdaPin<T>,
+    {
+        let i2c = I2c::new_async(i2c, scl, sda, irqs, Config::default());
+        Self { i2c }
+    }
+
+    /// Reads the current time as wall clock
+    ///
+    /// # Note
+    /// If no RTC is connected, the address is not acknowledged and this function fails with [`RtcError::Missing`].
+    pub async fn read(&mut self) -> Result<WallClock, RtcError> {
+        // Reject the time if the oscillator has been stopped since it was set
+        let mut status = [0];
+        self.i2c.write_read_async(DS3231_ADDRESS, [DS3231_STATUS], &mut status).await.map_err(Self::map_error)?;
+        if status[0] & DS3231_OSF != 0 {
+            return Err(RtcError::Invalid);
+        }
+
+        // Read the timekeeping registers
+        let mut time = [0; 7];
+        self.i2c.write_read_async(DS3231_ADDRESS, [DS3231_TIME], &mut time).await.map_err(Self::map_error)?;
+        let [seconds, minutes, hours, _weekday, day, month, year] = time;
+        let hours = match hours & DS3231_12H != 0 {
+            // Note: In 12-hour mode, bit 5 is the PM flag, and 12 AM is midnight
+            true => bcd_decode(hours & 0x1F) % 12 + 12 * ((hours >> 5) & 1) as u64,
+            false => bcd_decode(hours & 0x3F),
+        };
+        let century = if month & DS3231_CENTURY != 0 { 2100 } else { 2000 };
+        let (year, month, day) = (century + bcd_decode(year), bcd_decode(month & 0x1F), bcd_decode(day & 0x3F));
+        let (hours, minutes, seconds) = (hours, bcd_decode(minutes & 0x7F), bcd_decode(seconds & 0x7F));
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 59 {
+            // Reject corrupt registers
+            return Err(RtcError::Invalid);
+        }
+
+        // Convert to unix time
+        let days = days_from_civil(year, month, day);
+        Ok(WallClock::from_unix_secs(days * 86_400 + hours * 3600 + minutes * 60 + seconds))
+    }
+
+    /// Sets the RTC to the current time of the given wall clock, and clears the oscillator-stop flag
+    pub async fn set(&mut self, clock: &WallClock) -> Result<(), RtcError> {
+        // Convert unix time to civil date and time
+        // Note: The weekday register is not used, but is set with Monday as 1 for completeness
+        let unix_secs = clock.unix_secs();
+        let (days, secs) = (unix_secs / 86_400, unix_secs % 86_400);
+        let (year, month, day) = civil_from_days(days);
+        let century = if year >= 2100 { DS3231_CENTURY } else { 0 };
+        let weekday = (days + 3) % 7 + 1;
+
+        // Write the timekeeping registers in 24-hour mode
+        let time = [
+            DS3231_TIME,
+            bcd_encode(secs % 60),
+            bcd_encode(secs / 60 % 60),
+            bcd_encode(secs / 3600),
+            bcd_encode(weekday),
+            bcd_encode(day),
+            bcd_encode(month) | century,
+            bcd_encode(year % 100),
+        ];
+        self.i2c.write_async(DS3231_ADDRESS, time).await.map_err(Self::map_error)?;
+
+        // Clear the oscillator-stop flag, so the time is considered valid from now on
+        let mut status = [0];
+        self.i2c.write_read_async(DS3231_ADDRESS, [DS3231_STATUS], &mut status).await.map_err(Self::map_error)?;
+        let status = [DS3231_STATUS, status[0] & !DS3231_OSF];
+        self.i2c.write_async(DS3231_ADDRESS, status).await.map_err(Self::map_error)
+    }
+
+    /// Maps an I2C error, so that an unacknowledged address signals a missing RTC
+    fn map_error(error: Error) -> RtcError {
+        match error {
+            Error::Abort(AbortReason::NoAcknowledge) => RtcError::Missing,
+            error => RtcError::I2c(error),
+        }
+    }
+}
+
+/// Computes the sleep duration until the next wall-clock multiple of the given interval, or returns the interval as-is
+/// if no wall clock is available
+///
+/// # Note
+/// Slots are counted from the unix epoch, so intervals that evenly divide a day wake at the same UTC times every day,
+/// e.g. a six-hour interval wakes at 00:00, 06:00, 12:00 and 18:00. If the next slot is too close because the device
+/// woke up slightly early, the slot after that is used instead, so a drifting internal clock does not cause an extra
+/// cycle.
+pub fn align_sleep(clock: Option<WallClock>, interval: Duration) -> Duration {
+    let Some(clock) = clock else {
+        // Fall back to relative sleep
+        return interval;
+    };
+
+    // Compute the time until the next slot, and skip slots that are less than an eighth of the interval away
+    let interval_secs = interval.as_secs().max(1);
+    let remaining = interval_secs - clock.unix_secs() % interval_secs;
+    match remaining < interval_secs / 8 {
+        true => Duration::from_secs(remaining + interval_secs),
+        false => Duration::from_secs(remaining),
+    }
+}
+
+/// Decodes a BCD register value
+const fn bcd_decode(value: u8) -> u64 {
+    ((value >> 4) * 10 + (value & 0x0F)) as u64
+}
+
+/// Encodes a value below 100 as BCD register value
+const fn bcd_encode(value: u64) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
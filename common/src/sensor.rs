@@ -0,0 +1,386 @@
+//! Moisture sensor handling
+
+use crate::config::{SensorPin, SensorPolarity};
+use crate::readout::{SensorReadout, SensorStats, TemperatureUnit};
+use crate::{Irqs, debug_println};
+use core::fmt::{Display, Formatter};
+use core::mem::ManuallyDrop;
+use embassy_rp::adc::{Adc, AdcPin, Async, Channel, Config, Error};
+use embassy_rp::gpio::{Level, Output, Pin, Pull};
+use embassy_rp::peripherals::{ADC, ADC_TEMP_SENSOR, PIN_26, PIN_28};
+use embassy_rp::{Peri, PeripheralType, pac};
+use embassy_time::{Duration, Instant, Timer};
+use static_cell::ConstStaticCell;
+
+/// The default ADC clock divider for ~732 conversions per second across all channels (the lowest possible sample rate)
+const SAMPLE_RATE: u16 = u16::MAX;
+/// The maximum sample count per channel, which is also the default (~1.4s per sampled channel at the default rate)
+pub const SAMPLE_COUNT: usize = 1024;
+/// The ADC clock in MHz
+const ADC_CLOCK_MHZ: u64 = 48;
+/// The ADC clock cycles per conversion at the highest possible sample rate
+const ADC_CONVERSION_CYCLES: u64 = 96;
+/// The default amount of leading samples to discard, as they may still be skewed by the ADC sample-and-hold capacitor
+pub const WARMUP_SAMPLES: u16 = 32;
+/// The maximum amount of ADC channels (sensor, battery, temperature)
+const CHANNEL_COUNT: usize = 3;
+/// The interleaved sample buffer length
+const SAMPLE_BUF_LEN: usize = SAMPLE_COUNT * CHANNEL_COUNT;
+/// The maximum sample buffer size in bytes, so bumping the sample count cannot silently eat up the RAM
+const SAMPLE_BUF_MAX: usize = 8 * 1024;
+/// The ADC full-scale value (12 bit)
+const ADC_FULL_SCALE: f64 = 4096.0;
+/// The default ADC reference voltage in millivolts (the nominal `3V3` rail)
+pub const VREF_MILLIVOLTS: u16 = 3300;
+/// The default sensor voltage in millivolts below which a reading is implausible (shorted or disconnected sensor)
+pub const FAULT_LOW_MILLIVOLTS: u16 = 50;
+/// The default sensor voltage in millivolts above which a reading is implausible (shorted or disconnected sensor)
+pub const FAULT_HIGH_MILLIVOLTS: u16 = 3250;
+
+/// A sensor read error
+#[derive(Debug)]
+pub enum SensorError {
+    /// The ADC conversion failed
+    Adc(Error),
+    /// The DMA did not drain the ADC FIFO in time, so samples have been lost
+    DmaOverrun,
+}
+impl Display for SensorError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::Adc(error) => write!(f, "failed to read sensor channel: {error:?}"),
+            Self::DmaOverrun => write!(f, "failed to read sensor channels: dma did not keep up with the adc"),
+        }
+    }
+}
+
+/// The moisture sensor
+///
+/// # Important
+/// The battery voltage is sampled via `GPIO29`, which is shared with the radio SPI clock; so the sensor must only be used
+/// before the radio is booted or after it has been shut down.
+pub struct Sensor<'d, D>
+where
+    D: embassy_rp::dma::Channel + PeripheralType,
+{
+    /// ADC driver
+    adc: Adc<'d, Async>,
+    /// ADC DMA channel
+    dma: Peri<'d, D>,
+    /// The power-select pin to power up the sensor
+    ///
+    /// # Note
+    /// The pin is never released, as the pad would fall back to its default pull-down, which powers an active-low sensor.
+    powerselect: ManuallyDrop<Output<'d>>,
+    /// The active level of the power-select pin
+    polarity: SensorPolarity,
+    /// ADC channels
+    channels: Channels<'d>,
+    /// The interleaved sample buffer
+    samples: &'static mut [u16; SAMPLE_BUF_LEN],
+    /// The sample count per channel
+    sample_count: usize,
+    /// The ADC clock divider
+    sample_div: u16,
+}
+impl<'d, D> Sensor<'d, D>
+where
+    D: embassy_rp::dma::Channel + PeripheralType,
+{
+    /// The default maximum amount of time to wait to give the sensor enough time to power up
+    pub const POWERUP_DURATION: Duration = Duration::from_millis(500);
+    /// The default maximum raw ADC difference between two consecutive readings of a settled sensor (~6.4mV)
+    pub const SETTLE_TOLERANCE: u16 = 8;
+    /// The interval between two consecutive settle readings
+    const SETTLE_INTERVAL: Duration = Duration::from_millis(10);
+    /// The amount of consecutive readings within tolerance after which the sensor is considered settled
+    const SETTLE_COUNT: usize = 3;
+    /// The delay before retrying a sampling run that has been cut short by a DMA overrun
+    const OVERRUN_RETRY_DELAY: Duration = Duration::from_millis(10);
+    /// The power-down time before retrying an implausible reading, so the sensor is fully re-powered
+    const FAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+    /// Creates a new sensor instance with the given power-select polarity and sensor ADC channel, and samples the chip
+    /// temperature only if the temperature sensor is given
+    ///
+    /// # Panics
+    /// This function panics if it is called more than once, as the sample buffer is a singleton.
+    #[allow(clippy::too_many_arguments, reason = "every peripheral is passed separately to keep the pins explicit")]
+    pub fn new<P, B>(
+        adc: Peri<'d, ADC>,
+        irqs: Irqs,
+        dma: Peri<'d, D>,
+        powerselect: Peri<'d, P>,
+        polarity: SensorPolarity,
+        sensor: Channel<'d>,
+        temperature: Option<Peri<'d, ADC_TEMP_SENSOR>>,
+        battery: Peri<'d, B>,
+    ) -> Self
+    where
+        P: Pin,
+        B: AdcPin,
+    {
+        /// The static sample buffer
+        // Note: The buffer is kept off the task future, as it is too large to carry around
+        static SAMPLES: ConstStaticCell<[u16; SAMPLE_BUF_LEN]> = ConstStaticCell::new([0; SAMPLE_BUF_LEN]);
+        const { assert!(size_of::<[u16; SAMPLE_BUF_LEN]>() <= SAMPLE_BUF_MAX, "sample buffer is too large") };
+        const { assert!((WARMUP_SAMPLES as usize) < SAMPLE_COUNT, "warmup window is too large") };
+
+        // Setup ADC driver and channels
+        let adc = Adc::new(adc, irqs, Config::default());
+        let battery = Channel::new_pin(battery, Pull::None);
+        let channels = match temperature.map(Channel::new_temp_sensor) {
+            Some(temperature) => Channels::All([sensor, battery, temperature]),
+            None => Channels::NoTemperature([sensor, battery]),
+        };
+
+        // Setup power-select pin with the sensor powered down
+        let powerselect = ManuallyDrop::new(Output::new(powerselect, Self::power_level(polarity, false)));
+        let (sample_count, sample_div) = (SAMPLE_COUNT, SAMPLE_RATE);
+        Self { adc, dma, powerselect, polarity, channels, samples: SAMPLES.take(), sample_count, sample_div }
+    }
+
+    /// The effective duration of a sampling run, derived from the sample count, the amount of sampled channels and the
+    /// sample rate
+    ///
+    /// # Note
+    /// This excludes the settle time before sampling, and a reading takes twice as long if it is retried.
+    pub fn sample_duration(&self) -> Duration {
+        let conversions = (self.sample_count * self.channels.len()) as u64;
+        Duration::from_micros(conversions * (self.sample_div as u64 + 1) / ADC_CLOCK_MHZ)
+    }
+
+    /// Picks the sample count and rate so that a sampling run takes the given duration, or restores the defaults if the
+    /// duration is zero
+    ///
+    /// # Note
+    /// The full sample count is kept as long as possible for the best supersampling, and is only lowered if the
+    /// duration is too short even at the highest sample rate. As the default sample rate already is the lowest possible
+    /// one, durations above the default are capped; see [`Self::sample_duration`] for the effective duration.
+    pub fn set_sample_duration(&mut self, duration: Duration) {
+        if duration == Duration::MIN {
+            // Restore the defaults
+            (self.sample_count, self.sample_div) = (SAMPLE_COUNT, SAMPLE_RATE);
+            return;
+        }
+
+        // Compute the ADC clock cycles per conversion for the full sample count, then fit the count to that rate
+        // Note: The ADC converts at most every 96 cycles, and the divider is the cycles per conversion minus one
+        let (cycles, channels) = (duration.as_micros().saturating_mul(ADC_CLOCK_MHZ), self.channels.len() as u64);
+        let cycles_max = SAMPLE_RATE as u64 + 1;
+        let per_conversion = (cycles / (SAMPLE_COUNT as u64 * channels)).clamp(ADC_CONVERSION_CYCLES, cycles_max);
+        let sample_count = (cycles / (per_conversion * channels)).clamp(1, SAMPLE_COUNT as u64);
+        (self.sample_count, self.sample_div) = (sample_count as usize, (per_conversion - 1) as u16);
+    }
+
+    /// Reads the connected sensors with the default settle tolerance and power-up duration, discards the given amount of
+    /// leading warmup samples, and converts the readings with the given ADC reference voltage in millivolts
+    ///
+    /// # Note
+    /// If the sensor voltage is outside of the given plausible range in millivolts (where zero disables the respective
+    /// bound), the sensor is re-powered and read once more; if the retry is still implausible, the readout is flagged
+    /// as suspect.
+    pub async fn read(
+        &mut self,
+        warmup: u16,
+        vref_mv: u16,
+        fault_low_mv: u16,
+        fault_high_mv: u16,
+        stats: bool,
+        unit: TemperatureUnit,
+    ) -> Result<SensorReadout, SensorError> {
+        // Read the sensor and accept plausible readings
+        let is_plausible = |readout: &SensorReadout| {
+            let millivolts = readout.sensor * 1000.0;
+            (fault_low_mv == 0 || millivolts >= fault_low_mv as f64)
+                && (fault_high_mv == 0 || millivolts <= fault_high_mv as f64)
+        };
+        let readout =
+            self.read_with_settle(Self::SETTLE_TOLERANCE, Self::POWERUP_DURATION, warmup, vref_mv, stats, unit).await?;
+        if is_plausible(&readout) {
+            return Ok(readout);
+        }
+
+        // Retry once after keeping the sensor powered down for a moment, as a flaky contact may settle on reconnect
+        debug_println!("[warn] implausible sensor voltage: {}; retrying", readout.sensor);
+        Timer::after(Self::FAULT_RETRY_DELAY).await;
+        let mut readout =
+            self.read_with_settle(Self::SETTLE_TOLERANCE, Self::POWERUP_DURATION, warmup, vref_mv, stats, unit).await?;
+        readout.suspect = !is_plausible(&readout);
+        Ok(readout)
+    }
+
+    /// Reads the connected sensors once the sensor output is stable within the given raw ADC tolerance, or once the
+    /// maximum power-up duration has elapsed, discards the given amount of leading warmup samples, and converts the
+    /// readings with the given ADC reference voltage in millivolts and temperature unit, optionally including the
+    /// sensor sample statistics
+    ///
+    /// # Note
+    /// The warmup window is capped to the sample count, so that at least one sample per channel is always kept.
+    pub async fn read_with_settle(
+        &mut self,
+        tolerance: u16,
+        max_wait: Duration,
+        warmup: u16,
+        vref_mv: u16,
+        stats: bool,
+        unit: TemperatureUnit,
+    ) -> Result<SensorReadout, SensorError> {
+        // Power the sensor and wait until it has settled
+        self.powerselect.set_level(Self::power_level(self.polarity, true));
+        if let Err(error) = self.settle(tolerance, max_wait).await {
+            // Power down the sensor before reporting the error
+            self.powerselect.set_level(Self::power_level(self.polarity, false));
+            return Err(SensorError::Adc(error));
+        }
+
+        // Do some supersampling, and retry once if the DMA has been held up by a transient arbitration issue
+        let mut result = self.sample().await;
+        if let Err(SensorError::DmaOverrun) = result {
+            Timer::after(Self::OVERRUN_RETRY_DELAY).await;
+            result = self.sample().await;
+        }
+
+        // Power down the sensor *first*, then check the sampling result
+        // Note: This should never fail under normal conditions
+        self.powerselect.set_level(Self::power_level(self.polarity, false));
+        result?;
+
+        // Process and sum interleaved samples after the warmup window
+        let count = self.channels.as_mut_slice().len();
+        let warmup = (warmup as usize).min(self.sample_count - 1);
+        let samples = &self.samples[warmup * count..self.sample_count * count];
+        let rounds = samples.len() / count;
+        let sensor_total: u64 = samples.chunks_exact(count).map(|round| round[0] as u64).sum();
+        let battery_total: u64 = samples.chunks_exact(count).map(|round| round[1] as u64).sum();
+        let temperature_total: Option<u64> =
+            samples.chunks_exact(count).map(|round| round.get(2).map(|&t| t as u64)).sum();
+
+        // Compute mean raw values and convert them
+        let sensor_raw = (sensor_total as f64) / (rounds as f64);
+        let battery_raw = (battery_total as f64) / (rounds as f64);
+        let temperature_raw = temperature_total.map(|total| (total as f64) / (rounds as f64));
+        let vref = (vref_mv as f64) / 1000.0;
+        let temperature = temperature_raw.map(|raw| SensorReadout::temperature(raw, ADC_FULL_SCALE, vref, unit));
+        let sensor = SensorReadout::voltage(sensor_raw, ADC_FULL_SCALE, vref);
+        let battery = SensorReadout::battery(battery_raw, ADC_FULL_SCALE, vref);
+
+        // Compute the sensor sample statistics in a second pass over the kept samples, if requested
+        // Note: The integer variance is scaled by 256 before taking the square root, so the standard deviation keeps a
+        // resolution of 1/16 count without a floating point square root; the sums cannot overflow for 12 bit samples
+        let stats = stats.then(|| {
+            let sensor_samples = samples.chunks_exact(count).map(|round| round[0] as u64);
+            let (min, max, squares) = sensor_samples.fold((u64::MAX, u64::MIN, 0), |(min, max, squares), sample| {
+                (min.min(sample), max.max(sample), squares + sample * sample)
+            });
+            let rounds = rounds as u64;
+            let variance = (rounds * squares - sensor_total * sensor_total) * 256 / (rounds * rounds);
+            let stddev = (variance.isqrt() as f64) / 16.0;
+            SensorStats {
+                min: SensorReadout::voltage(min as f64, ADC_FULL_SCALE, vref),
+                max: SensorReadout::voltage(max as f64, ADC_FULL_SCALE, vref),
+                stddev: SensorReadout::voltage(stddev, ADC_FULL_SCALE, vref),
+            }
+        });
+
+        // Round the mean raw values for calibration
+        // Note: The mean of 12 bit samples always fits into 16 bits
+        let (sensor_raw, temperature_raw) = ((sensor_raw + 0.5) as u16, temperature_raw.map(|raw| (raw + 0.5) as u16));
+        Ok(SensorReadout { sensor, temperature, battery, sensor_raw, temperature_raw, suspect: false, stats })
+    }
+
+    /// Samples all channels into the sample buffer, or fails if the DMA did not drain the ADC FIFO in time
+    ///
+    /// # Note
+    /// Samples are stored interleaved, so the buffer holds one sample per channel and sampling round.
+    async fn sample(&mut self) -> Result<(), SensorError> {
+        // Clear stale FIFO flags, so only overruns of this run are detected
+        // Note: The flags are write-one-to-clear
+        pac::ADC.fcs().modify(|w| {
+            w.set_over(true);
+            w.set_under(true);
+        });
+
+        // Sample and check for lost samples
+        let channels = self.channels.as_mut_slice();
+        let samples = &mut self.samples[..self.sample_count * channels.len()];
+        let result = self.adc.read_many_multichannel(channels, samples, self.sample_div, self.dma.reborrow()).await;
+        result.map_err(SensorError::Adc)?;
+        match pac::ADC.fcs().read().over() {
+            true => Err(SensorError::DmaOverrun),
+            false => Ok(()),
+        }
+    }
+
+    /// Maps the given power state to the power-select pin level according to the polarity
+    const fn power_level(polarity: SensorPolarity, powered: bool) -> Level {
+        match (polarity, powered) {
+            (SensorPolarity::ActiveHigh, true) | (SensorPolarity::ActiveLow, false) => Level::High,
+            (SensorPolarity::ActiveHigh, false) | (SensorPolarity::ActiveLow, true) => Level::Low,
+        }
+    }
+
+    /// Polls the sensor channel until consecutive readings are within the given tolerance, or the maximum duration has
+    /// elapsed
+    async fn settle(&mut self, tolerance: u16, max_wait: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + max_wait;
+        let (mut last, mut stable) = (None, 0);
+        while stable < Self::SETTLE_COUNT && Instant::now() < deadline {
+            // Compare the current reading against the previous one
+            Timer::after(Self::SETTLE_INTERVAL).await;
+            let value = self.adc.read(&mut self.channels.as_mut_slice()[0]).await?;
+            stable = match last {
+                Some(last) if value.abs_diff(last) <= tolerance => stable + 1,
+                _ => 0,
+            };
+            last = Some(value);
+        }
+        Ok(())
+    }
+}
+impl<'d, D> Drop for Sensor<'d, D>
+where
+    D: embassy_rp::dma::Channel + PeripheralType,
+{
+    fn drop(&mut self) {
+        // Ensure the sensor is powered down, and keep driving the power-select pin in that state
+        self.powerselect.set_level(Self::power_level(self.polarity, false));
+    }
+}
+
+/// The sampled ADC channels
+///
+/// # Note
+/// The ADC samples the channels round-robin in ascending channel order, so the channels follow that order: The sensor on
+/// `GPIO26`/`GPIO28`, then the battery on `GPIO29`, then the temperature sensor.
+enum Channels<'d> {
+    /// The sensor, battery and temperature channels
+    All([Channel<'d>; CHANNEL_COUNT]),
+    /// The sensor and battery channels only
+    NoTemperature([Channel<'d>; CHANNEL_COUNT - 1]),
+}
+impl<'d> Channels<'d> {
+    /// The channels as slice
+    fn as_mut_slice(&mut self) -> &mut [Channel<'d>] {
+        match self {
+            Self::All(channels) => channels,
+            Self::NoTemperature(channels) => channels,
+        }
+    }
+
+    /// The amount of channels
+    const fn len(&self) -> usize {
+        match self {
+            Self::All(channels) => channels.len(),
+            Self::NoTemperature(channels) => channels.len(),
+        }
+    }
+}
+
+/// Creates the ADC channel for the sensor on the given GPIO pin
+pub fn sensor_channel<'d>(pin: SensorPin, gpio26: Peri<'d, PIN_26>, gpio28: Peri<'d, PIN_28>) -> Channel<'d> {
+    match pin {
+        SensorPin::Gpio26 => Channel::new_pin(gpio26, Pull::None),
+        SensorPin::Gpio28 => Channel::new_pin(gpio28, Pull::None),
+    }
+}
@@ -0,0 +1,45 @@
+//! Stack high-water mark measurement
+
+use core::ptr;
+use cortex_m::register::msp;
+
+/// The paint pattern for unused stack memory
+const PATTERN: u32 = 0x5AC5_5AC5;
+/// The amount of bytes below the current stack pointer that are left untouched while painting
+const PAINT_MARGIN: usize = 256;
+
+unsafe extern "C" {
+    /// The lowest stack address (provided by `cortex-m-rt`)
+    static _stack_end: u32;
+}
+
+/// Paints the unused stack region with a known pattern
+///
+/// # Note
+/// This should be called as early as possible, as stack memory used before painting is not measured.
+#[inline(never)]
+pub fn paint() {
+    // Compute the unused stack region below the current stack pointer
+    let start = (&raw const _stack_end).cast_mut();
+    let end = (msp::read() as usize).saturating_sub(PAINT_MARGIN) as *mut u32;
+
+    // Paint the region
+    // Note: The region is below the stack pointer and the heap is unused, so nothing else can access it
+    let mut cursor = start;
+    while cursor < end {
+        unsafe { ptr::write_volatile(cursor, PATTERN) };
+        cursor = cursor.wrapping_add(1);
+    }
+}
+
+/// Computes the amount of stack bytes that have never been used since painting
+pub fn free() -> usize {
+    // Count the untouched pattern words from the bottom of the stack
+    let start = &raw const _stack_end;
+    let end = msp::read() as usize as *const u32;
+    let mut cursor = start;
+    while cursor < end && unsafe { ptr::read_volatile(cursor) } == PATTERN {
+        cursor = cursor.wrapping_add(1);
+    }
+    cursor as usize - start as usize
+}
@@ -0,0 +1,176 @@
+//! Minimal WebSocket client transport to tunnel MQTT over WebSocket
+
+use crate::mqtt_codec::MqttBuffer;
+use crate::websocket_codec::{self, Base64, FrameHeader, KEY_LEN};
+use core::fmt::Write as _;
+use embassy_rp::clocks::RoscRng;
+use embedded_io_async::{Error, ErrorKind, ErrorType, Read, ReadExactError, Write};
+
+/// The maximum size of the HTTP upgrade request and response header
+const HANDSHAKE_BUF_SIZE: usize = 512;
+/// The binary frame opcode
+const OPCODE_BINARY: u8 = 0x2;
+/// The continuation frame opcode
+const OPCODE_CONTINUATION: u8 = 0x0;
+/// The close frame opcode
+const OPCODE_CLOSE: u8 = 0x8;
+/// The ping frame opcode
+const OPCODE_PING: u8 = 0x9;
+/// The pong frame opcode
+const OPCODE_PONG: u8 = 0xA;
+/// The maximum payload length of control frames
+const CONTROL_PAYLOAD_MAX: usize = 125;
+
+/// A WebSocket connection that tunnels a byte stream in binary frames over the given transport
+///
+/// # Note
+/// Every write is sent as a single binary frame, and incoming frames are reassembled into a plain byte stream.
+pub struct WebSocket<T> {
+    /// The underlying transport
+    inner: T,
+    /// The remaining payload length of the current incoming data frame
+    remaining: usize,
+    /// Whether the server has closed the connection
+    closed: bool,
+}
+impl<T> WebSocket<T>
+where
+    T: Read + Write,
+{
+    /// Performs the HTTP upgrade handshake for the `mqtt` subprotocol on the given, already connected transport
+    ///
+    /// # Note
+    /// This validates the status line and the `Sec-WebSocket-Accept` header of the response.
+    pub async fn connect(mut inner: T, host: &str, path: &str) -> Result<Self, ErrorKind> {
+        // Build the upgrade request with a random key
+        let mut nonce = [0; 16];
+        RoscRng.fill_bytes(&mut nonce);
+        let key = MqttBuffer::<KEY_LEN>::try_from_display(Base64(&nonce)).map_err(|_| ErrorKind::OutOfMemory)?;
+        let mut request = MqttBuffer::<HANDSHAKE_BUF_SIZE>::new();
+        write!(&mut request, "GET {path} HTTP/1.1\r\nHost: {host}\r\n").map_err(|_| ErrorKind::OutOfMemory)?;
+        write!(&mut request, "Upgrade: websocket\r\nConnection: Upgrade\r\n").map_err(|_| ErrorKind::OutOfMemory)?;
+        write!(&mut request, "Sec-WebSocket-Key: ").map_err(|_| ErrorKind::OutOfMemory)?;
+        request.try_extend(&key).map_err(|_| ErrorKind::OutOfMemory)?;
+        write!(&mut request, "\r\n").map_err(|_| ErrorKind::OutOfMemory)?;
+        write!(&mut request, "Sec-WebSocket-Protocol: mqtt\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .map_err(|_| ErrorKind::OutOfMemory)?;
+
+        // Send the request
+        inner.write_all(&request).await.map_err(|e| e.kind())?;
+        inner.flush().await.map_err(|e| e.kind())?;
+
+        // Read the response header bytewise, so we don't consume any frame data
+        let mut response = MqttBuffer::<HANDSHAKE_BUF_SIZE>::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            inner.read_exact(&mut byte).await.map_err(Self::map_read_error)?;
+            response.try_extend(&byte).map_err(|_| ErrorKind::OutOfMemory)?;
+        }
+
+        // Validate the response
+        websocket_codec::validate_response(&response, &websocket_codec::accept(&key))?;
+        Ok(Self { inner, remaining: 0, closed: false })
+    }
+
+    /// Reads the header of the next incoming frame, and returns the opcode and payload length
+    async fn read_header(&mut self) -> Result<(u8, usize), ErrorKind> {
+        // Read the fixed header and the extended payload length if any
+        let mut header = [0; 10];
+        self.inner.read_exact(&mut header[..2]).await.map_err(Self::map_read_error)?;
+        let len = FrameHeader::header_len([header[0], header[1]]);
+        self.inner.read_exact(&mut header[2..len]).await.map_err(Self::map_read_error)?;
+
+        // Parse the header
+        let FrameHeader { opcode, len } = FrameHeader::parse(&header[..len])?;
+        Ok((opcode, len))
+    }
+
+    /// Writes a single masked frame with the given opcode and payload
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), ErrorKind> {
+        /// The chunk size to mask the payload in
+        const CHUNK_SIZE: usize = 64;
+
+        // Write the header with a random masking key
+        // Note: Client frames must always be masked
+        let mask = RoscRng.next_u32().to_be_bytes();
+        let (header, len) = FrameHeader { opcode, len: payload.len() }.encode(mask);
+        self.inner.write_all(&header[..len]).await.map_err(|e| e.kind())?;
+
+        // Mask and write the payload chunkwise
+        let mut chunk = [0; CHUNK_SIZE];
+        for (index, source) in payload.chunks(CHUNK_SIZE).enumerate() {
+            let chunk = &mut chunk[..source.len()];
+            chunk.copy_from_slice(source);
+            websocket_codec::apply_mask(chunk, mask, index * CHUNK_SIZE);
+            self.inner.write_all(chunk).await.map_err(|e| e.kind())?;
+        }
+        Ok(())
+    }
+
+    /// Maps a read error, so that an unexpected EOF becomes an aborted connection
+    fn map_read_error(error: ReadExactError<T::Error>) -> ErrorKind {
+        match error {
+            ReadExactError::UnexpectedEof => ErrorKind::ConnectionAborted,
+            ReadExactError::Other(error) => error.kind(),
+        }
+    }
+}
+impl<T> ErrorType for WebSocket<T> {
+    type Error = ErrorKind;
+}
+impl<T> Read for WebSocket<T>
+where
+    T: Read + Write,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // Process frames until we have payload data to return
+        while self.remaining == 0 && !self.closed && !buf.is_empty() {
+            let (opcode, len) = self.read_header().await?;
+            match opcode {
+                OPCODE_BINARY | OPCODE_CONTINUATION => self.remaining = len,
+                OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG if len <= CONTROL_PAYLOAD_MAX => {
+                    // Read the control payload
+                    let mut payload = [0; CONTROL_PAYLOAD_MAX];
+                    let payload = &mut payload[..len];
+                    self.inner.read_exact(payload).await.map_err(Self::map_read_error)?;
+
+                    // Answer pings and close frames, and ignore pongs
+                    match opcode {
+                        OPCODE_PING => self.write_frame(OPCODE_PONG, payload).await?,
+                        OPCODE_CLOSE => {
+                            // Echo the close frame and signal the end of the stream
+                            self.write_frame(OPCODE_CLOSE, payload).await?;
+                            self.closed = true;
+                        }
+                        _ => (),
+                    }
+                }
+                // Note: Text frames and oversized control frames are not valid for MQTT over WebSocket
+                _ => return Err(ErrorKind::InvalidData),
+            }
+        }
+
+        // Read the payload data
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            // Nothing to read or end of stream
+            return Ok(0);
+        }
+        let read = self.inner.read(&mut buf[..len]).await.map_err(|e| e.kind())?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+impl<T> Write for WebSocket<T>
+where
+    T: Read + Write,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_frame(OPCODE_BINARY, buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await.map_err(|e| e.kind())
+    }
+}
@@ -0,0 +1,347 @@
+//! WebSocket handshake and frame coding, independent of the network stack, so it can be tested on the host
+
+use crate::mqtt_codec::MqttBuffer;
+use core::fmt::{Display, Formatter};
+use embedded_io_async::ErrorKind;
+
+/// The length of a base64 encoded `Sec-WebSocket-Key` for a 16 byte nonce
+pub const KEY_LEN: usize = 24;
+/// The length of a base64 encoded `Sec-WebSocket-Accept` SHA-1 digest
+pub const ACCEPT_LEN: usize = 28;
+/// The maximum length of a frame header including the masking key
+pub const HEADER_LEN_MAX: usize = 14;
+
+/// The GUID that is appended to the key to compute the accept value
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Computes the expected `Sec-WebSocket-Accept` value for the given base64 encoded `Sec-WebSocket-Key`
+pub fn accept(key: &[u8]) -> MqttBuffer<ACCEPT_LEN> {
+    let digest = sha1(&[key, ACCEPT_GUID]);
+    MqttBuffer::from_display(Base64(&digest))
+}
+
+/// Validates the given HTTP upgrade response header against the expected `Sec-WebSocket-Accept` value
+///
+/// # Note
+/// The header name is matched case-insensitively, as required for HTTP header fields.
+pub fn validate_response(response: &[u8], accept: &[u8]) -> Result<(), ErrorKind> {
+    /// The header field that carries the accept value
+    const ACCEPT_FIELD: &[u8] = b"sec-websocket-accept:";
+
+    // Validate the status line
+    let mut lines = response.split(|byte| *byte == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let status = lines.next().unwrap_or_default();
+    if status != b"HTTP/1.1 101" && !status.starts_with(b"HTTP/1.1 101 ") {
+        return Err(ErrorKind::ConnectionRefused);
+    }
+
+    // Find and validate the accept header
+    for line in lines {
+        if let Some(value) = line.get(ACCEPT_FIELD.len()..)
+            && line[..ACCEPT_FIELD.len()].eq_ignore_ascii_case(ACCEPT_FIELD)
+        {
+            return match value.trim_ascii() == accept {
+                true => Ok(()),
+                false => Err(ErrorKind::ConnectionRefused),
+            };
+        }
+    }
+    Err(ErrorKind::ConnectionRefused)
+}
+
+/// A WebSocket frame header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The frame opcode
+    pub opcode: u8,
+    /// The payload length
+    pub len: usize,
+}
+impl FrameHeader {
+    /// Returns the total length of an unmasked header, given its first two bytes
+    pub const fn header_len(fixed: [u8; 2]) -> usize {
+        match fixed[1] & 0x7F {
+            126 => 4,
+            127 => 10,
+            _ => 2,
+        }
+    }
+
+    /// Parses an incoming header, where `header` has the length that is returned by [`Self::header_len`]
+    ///
+    /// # Note
+    /// Frames from the server must not be masked, so masked frames are rejected as invalid.
+    pub fn parse(header: &[u8]) -> Result<Self, ErrorKind> {
+        // Parse the fixed header
+        let [first, second, extended @ ..] = header else {
+            return Err(ErrorKind::InvalidData);
+        };
+        if second & 0x80 != 0 {
+            return Err(ErrorKind::InvalidData);
+        }
+
+        // Parse the extended payload length if any
+        let len = match (second & 0x7F, extended) {
+            (126, [hi, lo]) => u16::from_be_bytes([*hi, *lo]) as u64,
+            (127, extended) => u64::from_be_bytes(extended.try_into().map_err(|_| ErrorKind::InvalidData)?),
+            (len @ ..126, []) => len as u64,
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        let len = usize::try_from(len).map_err(|_| ErrorKind::InvalidData)?;
+        Ok(Self { opcode: first & 0x0F, len })
+    }
+
+    /// Encodes the header of a final, masked outgoing frame with the given masking key, and returns the buffer and the
+    /// used header length
+    pub fn encode(&self, mask: [u8; 4]) -> ([u8; HEADER_LEN_MAX], usize) {
+        // Assemble the header with the payload length
+        let mut header = [0; HEADER_LEN_MAX];
+        header[0] = 0x80 | self.opcode;
+        let len = match self.len {
+            len @ ..126 => {
+                header[1] = 0x80 | len as u8;
+                2
+            }
+            len @ ..=0xFFFF => {
+                header[1] = 0x80 | 126;
+                header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+                4
+            }
+            len => {
+                header[1] = 0x80 | 127;
+                header[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+                10
+            }
+        };
+
+        // Append the masking key
+        header[len..len + 4].copy_from_slice(&mask);
+        (header, len + 4)
+    }
+}
+
+/// Masks or unmasks the given payload data in place, where `offset` is the position of `data` within the payload
+pub fn apply_mask(data: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (pos, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[(offset + pos) % 4];
+    }
+}
+
+/// Displays the given bytes as padded base64
+pub struct Base64<'a>(pub &'a [u8]);
+impl Display for Base64<'_> {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        use core::fmt::Write;
+
+        /// The base64 alphabet
+        const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        for chunk in self.0.chunks(3) {
+            // Split the chunk into 6 bit groups and pad incomplete chunks
+            let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+            let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for index in 0..4 {
+                let char_ = match index <= chunk.len() {
+                    true => ALPHABET[(group >> (18 - 6 * index)) as usize & 0x3F] as char,
+                    false => '=',
+                };
+                f.write_char(char_)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the SHA-1 digest over the concatenation of the given parts
+///
+/// # Note
+/// SHA-1 is only used for the `Sec-WebSocket-Accept` check, which is a protocol sanity check and not a security
+/// property, so a compact implementation is sufficient.
+pub fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+    /// The block size in bytes
+    const BLOCK_SIZE: usize = 64;
+
+    // Process all full blocks
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let (mut block, mut filled, mut total) = ([0; BLOCK_SIZE], 0, 0u64);
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        block[filled] = *byte;
+        (filled, total) = (filled + 1, total + 1);
+        if filled == BLOCK_SIZE {
+            sha1_block(&mut state, &block);
+            filled = 0;
+        }
+    }
+
+    // Pad the last block with the terminating bit and the message length in bits
+    block[filled] = 0x80;
+    block[filled + 1..].fill(0);
+    if filled + 1 > BLOCK_SIZE - 8 {
+        // The length does not fit into the current block anymore
+        sha1_block(&mut state, &block);
+        block.fill(0);
+    }
+    block[BLOCK_SIZE - 8..].copy_from_slice(&(total * 8).to_be_bytes());
+    sha1_block(&mut state, &block);
+
+    // Serialize the state
+    let mut digest = [0; 20];
+    for (target, word) in digest.chunks_exact_mut(4).zip(state) {
+        target.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Processes a single SHA-1 block
+fn sha1_block(state: &mut [u32; 5], block: &[u8; 64]) {
+    // Expand the message schedule
+    let mut schedule = [0u32; 80];
+    for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for index in 16..80 {
+        let word = schedule[index - 3] ^ schedule[index - 8] ^ schedule[index - 14] ^ schedule[index - 16];
+        schedule[index] = word.rotate_left(1);
+    }
+
+    // Run the compression rounds
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (index, word) in schedule.into_iter().enumerate() {
+        let (f, k) = match index {
+            0..20 => ((b & c) | (!b & d), 0x5A827999),
+            20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+            40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6),
+        };
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+        (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+    }
+
+    // Add the compressed block to the state
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the given bytes as base64 string
+    fn base64(bytes: &[u8]) -> String {
+        Base64(bytes).to_string()
+    }
+
+    /// Formats the given digest as lowercase hex string
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn base64_rfc4648() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0; 16]).len(), KEY_LEN);
+    }
+
+    #[test]
+    fn sha1_vectors() {
+        assert_eq!(hex(&sha1(&[])), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(&[b"abc"])), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        // 56 bytes need an extra block for the length
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(hex(&sha1(&[message])), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex(&sha1(&[&message[..7], &message[7..]])), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn accept_rfc6455() {
+        assert_eq!(&*accept(b"dGhlIHNhbXBsZSBub25jZQ=="), b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn response_valid() {
+        let accept = b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            sec-websocket-accept:  s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: mqtt\r\n\r\n";
+        assert!(validate_response(response, accept).is_ok());
+
+        let response = b"HTTP/1.1 101\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert!(validate_response(response, accept).is_ok());
+    }
+
+    #[test]
+    fn response_invalid() {
+        let accept = b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+        let wrong_status = b"HTTP/1.1 200 OK\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert_eq!(validate_response(wrong_status, accept), Err(ErrorKind::ConnectionRefused));
+
+        let wrong_accept =
+            b"HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        assert_eq!(validate_response(wrong_accept, accept), Err(ErrorKind::ConnectionRefused));
+
+        let missing_accept = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        assert_eq!(validate_response(missing_accept, accept), Err(ErrorKind::ConnectionRefused));
+    }
+
+    #[test]
+    fn header_parse() {
+        // The unmasked "Hello" example from RFC 6455
+        let header = [0x81, 0x05];
+        assert_eq!(FrameHeader::header_len(header), 2);
+        assert_eq!(FrameHeader::parse(&header), Ok(FrameHeader { opcode: 0x1, len: 5 }));
+
+        // The 256 byte and 64 KiB binary examples from RFC 6455
+        let header = [0x82, 0x7E, 0x01, 0x00];
+        assert_eq!(FrameHeader::header_len([header[0], header[1]]), 4);
+        assert_eq!(FrameHeader::parse(&header), Ok(FrameHeader { opcode: 0x2, len: 256 }));
+
+        let header = [0x82, 0x7F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        assert_eq!(FrameHeader::header_len([header[0], header[1]]), 10);
+        assert_eq!(FrameHeader::parse(&header), Ok(FrameHeader { opcode: 0x2, len: 65536 }));
+    }
+
+    #[test]
+    fn header_parse_invalid() {
+        // Masked server frames and truncated headers are rejected
+        assert_eq!(FrameHeader::parse(&[0x81, 0x85]), Err(ErrorKind::InvalidData));
+        assert_eq!(FrameHeader::parse(&[0x82, 0x7E, 0x01]), Err(ErrorKind::InvalidData));
+        assert_eq!(FrameHeader::parse(&[0x82]), Err(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn header_encode() {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let (header, len) = FrameHeader { opcode: 0x1, len: 5 }.encode(mask);
+        assert_eq!(&header[..len], [0x81, 0x85, 0x37, 0xFA, 0x21, 0x3D]);
+
+        let (header, len) = FrameHeader { opcode: 0x2, len: 256 }.encode(mask);
+        assert_eq!(&header[..len], [0x82, 0xFE, 0x01, 0x00, 0x37, 0xFA, 0x21, 0x3D]);
+
+        let (header, len) = FrameHeader { opcode: 0x2, len: 65536 }.encode(mask);
+        assert_eq!(&header[..len], [0x82, 0xFF, 0, 0, 0, 0, 0, 0x01, 0, 0, 0x37, 0xFA, 0x21, 0x3D]);
+    }
+
+    #[test]
+    fn mask_rfc6455() {
+        // The masked "Hello" example from RFC 6455
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut payload = *b"Hello";
+        apply_mask(&mut payload, mask, 0);
+        assert_eq!(payload, [0x7F, 0x9F, 0x4D, 0x51, 0x58]);
+
+        // Masking chunkwise with offsets yields the same result, and masking again restores the payload
+        let (mut head, mut tail) = (*b"Hel", *b"lo");
+        apply_mask(&mut head, mask, 0);
+        apply_mask(&mut tail, mask, 3);
+        assert_eq!([head.as_slice(), tail.as_slice()].concat(), payload);
+        apply_mask(&mut payload, mask, 0);
+        assert_eq!(&payload, b"Hello");
+    }
+}
@@ -1,9 +1,14 @@
 //! Wifi magic
 
-use crate::Irqs;
+use crate::config::WifiAuth;
+use crate::debug_println;
+use crate::{APP_TIMEOUT, Irqs};
 use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
 use core::sync::atomic::{AtomicBool, Ordering};
-use cyw43::{Control, JoinOptions, PowerManagementMode, SpiBusCyw43, State};
+use cyw43::{
+    Control, ControlError, JoinAuth, JoinOptions, PowerManagementMode, ScanOptions, ScanType, SpiBusCyw43, State,
+};
 use cyw43_firmware::{CYW43_43439A0, CYW43_43439A0_CLM};
 use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
 use embassy_executor::Spawner;
@@ -15,8 +20,16 @@ use embassy_rp::peripherals::{DMA_CH0, PIO0};
 use embassy_rp::pio::{Pio, PioPin};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::Timer;
+use embassy_time::{Duration, Timer, with_timeout};
 use embedded_hal::digital::{ErrorType, OutputPin};
+use heapless::String;
+
+/// The maximum time to spend scanning for access points
+const SCAN_TIMEOUT: Duration = Duration::from_secs(3);
+/// The maximum time to spend joining a network, so we can give up before the watchdog kicks in
+const JOIN_TIMEOUT: Duration = Duration::from_ticks(APP_TIMEOUT.as_ticks() / 3);
+/// The maximum amount of access points collected during a scan
+pub const SCAN_CAPACITY: usize = 4;
 
 /// [`cyw43::Runner`] for [`Pio0Dma0Spi`]
 type Cyw43Runner = cyw43::Runner<'static, &'static SharedOutput, &'static mut Pio0Dma0Spi>;
@@ -72,13 +85,13 @@ pub struct Cyw43ConfigWithPio {
 }
 impl Cyw43ConfigWithPio {
     /// Sets the power-select line and the chip-select, data and clock SPI lines
-    pub fn set_pins<P, S, D, C>(
+    pub fn set_pins<P, S, D, C, const SOCKETS: usize>(
         self,
         powerselect: Peri<'static, P>,
         select: Peri<'static, S>,
         data: Peri<'static, D>,
         clock: Peri<'static, C>,
-    ) -> Cyw43
+    ) -> Cyw43<SOCKETS>
     where
         P: Pin,
         S: Pin,
@@ -104,8 +117,11 @@ impl Cyw43ConfigWithPio {
     }
 }
 
-/// Fully configured CYW43 peripheral
-pub struct Cyw43 {
+/// Fully configured CYW43 peripheral with a network stack pool for `SOCKETS` sockets
+///
+/// # Note
+/// The default MQTT flow needs three sockets: one for DHCP, one for NTP and one for the MQTT connection.
+pub struct Cyw43<const SOCKETS: usize> {
     /// The power-select line
     powerselect: SharedOutput,
     /// The PIO-driven SPI bus
@@ -113,11 +129,11 @@ pub struct Cyw43 {
     /// Radio state
     state: State,
     /// Network stack pool
-    stack: StackResources<5>,
+    stack: StackResources<SOCKETS>,
     /// Stop signal
     stop: AtomicBool,
 }
-impl Cyw43 {
+impl<const SOCKETS: usize> Cyw43<SOCKETS> {
     /// Starts the CYW43 chip and initializes the firmware and network stack
     pub async fn boot(&'static mut self, spawner: &Spawner) -> (Cyw43Session, Stack<'static>) {
         // Start the CYW43 peripheral
@@ -151,6 +167,37 @@ impl Cyw43 {
     }
 }
 
+/// An access point found during a scan
+#[derive(Debug, Clone, Copy)]
+pub struct AccessPoint {
+    /// The BSSID of the access point
+    pub bssid: [u8; 6],
+    /// The signal strength in dBm
+    pub rssi: i32,
+    /// The wifi channel
+    pub channel: u8,
+}
+
+/// A wifi join error
+#[derive(Debug)]
+pub enum JoinError {
+    /// No access point broadcasts the configured SSID
+    NotFound,
+    /// The radio rejected the join, e.g. due to a wrong password
+    Rejected(ControlError),
+    /// The join did not complete in time
+    Timeout,
+}
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "failed to find wifi network"),
+            Self::Rejected(error) => write!(f, "failed to join wifi network: status {}", error.status),
+            Self::Timeout => write!(f, "timed out joining wifi network"),
+        }
+    }
+}
+
 /// A [`Cyw43`] session
 pub struct Cyw43Session {
     /// The power-select line
@@ -161,10 +208,85 @@ pub struct Cyw43Session {
     radio: Mutex<ThreadModeRawMutex, Control<'static>>,
 }
 impl Cyw43Session {
-    /// Joins the given wifi network
-    pub async fn join(&self, config: &crate::AppConfig) {
-        let options = JoinOptions::new(config.WIFI_PASS.as_bytes());
-        self.radio.lock().await.join(config.WIFI_SSID, options).await.expect("failed to join wifi network")
+    /// Joins the given wifi network, or fails if the join is rejected or takes longer than [`JOIN_TIMEOUT`]
+    pub async fn join(&self, config: &crate::config::AppConfig) -> Result<(), JoinError> {
+        // Select the authentication mode
+        let mut options = JoinOptions::new(config.WIFI_PASS.as_bytes());
+        match config.WIFI_AUTH {
+            WifiAuth::Auto if config.WIFI_PASS.is_empty() => options = JoinOptions::new_open(),
+            WifiAuth::Auto | WifiAuth::Wpa2Wpa3 => options.auth = JoinAuth::Wpa2Wpa3,
+            WifiAuth::Open => options = JoinOptions::new_open(),
+            WifiAuth::Wpa2 => options.auth = JoinAuth::Wpa2,
+            WifiAuth::Wpa3 => options.auth = JoinAuth::Wpa3,
+        }
+
+        // Join the network
+        let mut radio = self.radio.lock().await;
+        match with_timeout(JOIN_TIMEOUT, radio.join(config.WIFI_SSID, options)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => Err(JoinError::Rejected(error)),
+            Err(_) => Err(JoinError::Timeout),
+        }
+    }
+
+    /// Scans for access points of the given SSID
+    ///
+    /// # Note
+    /// The scan is bounded by [`SCAN_TIMEOUT`]; if more than [`SCAN_CAPACITY`] access points are found, only the
+    /// strongest ones are retained. The scan probes for the SSID directly, which is faster than a broadcast scan and
+    /// also finds access points with a hidden SSID.
+    pub async fn scan(&self, ssid: &str) -> [Option<AccessPoint>; SCAN_CAPACITY] {
+        // Start an active scan directed at our SSID
+        // Note: An SSID is at most 32 bytes, so a longer SSID is not found anyway
+        let mut options = ScanOptions::default();
+        options.scan_type = ScanType::Active;
+        options.ssid = Some(String::try_from(ssid).unwrap_or_default());
+        let mut radio = self.radio.lock().await;
+        let mut scanner = radio.scan(options).await;
+
+        // Collect all access points for our SSID
+        let mut access_points: [Option<AccessPoint>; SCAN_CAPACITY] = [None; SCAN_CAPACITY];
+        let scan = async {
+            while let Some(bss) = scanner.next().await {
+                // Filter by SSID, in case the radio also reports responses to other probes
+                let bss_ssid = bss.ssid.get(..bss.ssid_len as usize).unwrap_or_default();
+                if bss_ssid != ssid.as_bytes() {
+                    continue;
+                }
+
+                // Replace the weakest slot if the new access point is stronger
+                // Note: Empty slots compare as weakest since `None < Some(_)`, and the lower chanspec byte is the channel
+                let access_point = AccessPoint { bssid: bss.bssid, rssi: bss.rssi as i32, channel: bss.chanspec as u8 };
+                let weakest = access_points.iter_mut().min_by_key(|slot| slot.map(|slot| slot.rssi));
+                if let Some(weakest) = weakest.filter(|slot| slot.is_none_or(|slot| slot.rssi < access_point.rssi)) {
+                    *weakest = Some(access_point);
+                }
+            }
+        };
+        if with_timeout(SCAN_TIMEOUT, scan).await.is_err() {
+            // Log warning and use what we have so far
+            debug_println!("[warn] wifi scan timed out");
+        }
+        access_points
+    }
+
+    /// Scans for the configured wifi network and joins it, and returns the strongest access point found by the scan
+    ///
+    /// # Note
+    /// The CYW43 driver cannot join a specific BSSID, so the association itself is still performed via SSID and left
+    /// to the radio firmware, which prefers the strongest access point too. The scan ensures that we fail fast if the
+    /// network is not in range at all. The driver does not expose the RSSI of the current association either, so the
+    /// RSSI of the strongest access point doubles as signal strength of the connection.
+    pub async fn join_best(&self, config: &crate::config::AppConfig) -> Result<AccessPoint, JoinError> {
+        // Select the strongest access point
+        let access_points = self.scan(config.WIFI_SSID).await;
+        let best = access_points.iter().flatten().max_by_key(|access_point| access_point.rssi);
+        let best = *best.ok_or(JoinError::NotFound)?;
+        debug_println!("[info] best access point: {:02x?} (ch {}, {} dBm)", best.bssid, best.channel, best.rssi);
+
+        // Join network
+        self.join(config).await?;
+        Ok(best)
     }
 
     /// Performs a shutdown of the CYW43 chip
@@ -1,4 +1,15 @@
+use std::process::Command;
+
 fn main() {
     // Recompile if linker script changed
     println!("cargo:rerun-if-changed=memory.x");
+
+    // Export the git commit hash, or `unknown` if we are not built from a git checkout
+    // Note: The ref files are watched so a new commit triggers a rebuild
+    let output = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output();
+    let hash =
+        output.ok().filter(|output| output.status.success()).and_then(|output| String::from_utf8(output.stdout).ok());
+    println!("cargo:rustc-env=GIT_HASH={}", hash.as_deref().map(str::trim).unwrap_or("unknown"));
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
 }
@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 256
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 256 - 4k
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 256 - 8k
+    HISTORY : ORIGIN = 0x101FE000, LENGTH = 4k
     DATA  : ORIGIN = 0x101FF000, LENGTH = 4k
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K + 8K
 }
@@ -22,3 +23,11 @@ SECTIONS {
         KEEP(*(.userdata));
     } > DATA
 }
+
+SECTIONS {
+    /* Persistent reading history section */
+    .history (NOLOAD) :
+    {
+        KEEP(*(.history));
+    } > HISTORY
+}
@@ -0,0 +1,19 @@
+//! Device identification
+
+use common::device::DeviceId;
+use embassy_rp::Peri;
+use embassy_rp::flash::{Blocking, Flash};
+use embassy_rp::peripherals::FLASH;
+
+/// Reads the device ID
+///
+/// # Note
+/// The RP2040 has no unique chip ID, so this uses the unique ID of the external SPI flash instead.
+pub fn read_id(flash: Peri<'_, FLASH>) -> DeviceId {
+    // Read the unique flash ID
+    // Note: The flash size is irrelevant for reading the ID
+    let mut flash = Flash::<_, Blocking, 0>::new_blocking(flash);
+    let mut id = [0; 8];
+    flash.blocking_unique_id(&mut id).expect("failed to read unique flash id");
+    DeviceId::new(id)
+}
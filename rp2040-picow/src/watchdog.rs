@@ -1,7 +1,9 @@
 //! Provides reset-after functionality
 
-use crate::debug_println;
+use common::debug_println;
+use common::lifecycle::Starvation;
 use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
 use core::sync::atomic::{AtomicU32, Ordering};
 use core::u32;
 use cortex_m::asm;
@@ -12,39 +14,75 @@ use embassy_rp::pac::clocks::vals::{ClkRefCtrlSrc, ClkRtcCtrlAuxsrc, ClkSysCtrlS
 use embassy_rp::pac::psm::regs::Wdsel;
 use embassy_rp::pac::rosc::vals::Enable;
 use embassy_rp::pac::watchdog::regs::Load;
-use embassy_rp::pac::{CLOCKS, Interrupt, PLL_SYS, PLL_USB, PSM, ROSC, WATCHDOG, XIP_CTRL};
+use embassy_rp::pac::{
+    CLOCKS, Interrupt, PLL_SYS, PLL_USB, PSM, ROSC, RTC, VREG_AND_CHIP_RESET, WATCHDOG, XIP_CTRL, XOSC,
+};
 use embassy_rp::peripherals::{RTC, WATCHDOG};
 use embassy_rp::rtc::{DateTime, DateTimeFilter, DayOfWeek, Rtc};
 use embassy_rp::{Peri, interrupt};
-use embassy_time::{Duration, Instant, Timer};
+use embassy_time::{Duration, Instant, Timer, block_for};
 
-/// Lifecycle hints that persist across resets
-#[derive(Debug, Clone, Copy)]
-pub struct Lifecycle;
-impl Lifecycle {
-    /// The watchdog has been started, but not much more happened
-    pub const WATCHDOG: u32 = 367300213;
-    /// The radio peripheral has been initialized
-    pub const RADIOINIT: u32 = 968074460;
-    /// The main application logic has been entered
-    pub const APPINIT: u32 = 3422455895;
-    /// The main application logic has finished
-    pub const LIGHTSLEEP: u32 = 156439317;
-
-    /// The scratch checksum XOR constant
-    const CHECKSUM_XOR: u32 = 0x2144DF9C;
-
-    /// Persists the current lifecycle
-    pub fn store(lifecycle: u32) {
-        WATCHDOG.scratch0().write_value(lifecycle);
-        WATCHDOG.scratch1().write_value(lifecycle ^ Self::CHECKSUM_XOR);
+/// The hardware cause of the last reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    /// A power-on or brown-out reset
+    ///
+    /// # Note
+    /// The RP2040 does not distinguish brown-outs from power-on resets. Both clear the scratch registers though, so they
+    /// are never mistaken for a crash.
+    PowerOn,
+    /// A reset via the `RUN` pin
+    RunPin,
+    /// A reset requested by the debugger
+    Debugger,
+    /// A watchdog timeout, e.g. because the firmware hung
+    WatchdogTimeout,
+    /// A reset triggered via the watchdog, e.g. a graceful reset after sleep
+    WatchdogTrigger,
+    /// No hardware cause is recorded, e.g. after a software system reset
+    Unknown,
+}
+impl ResetCause {
+    /// Reads the cause of the last reset from the watchdog and chip reset registers
+    ///
+    /// # Note
+    /// The chip reset register is only updated on chip-level resets, so it is checked after the watchdog reason. After a
+    /// software system reset (like the post-panic reset), it may still report the last power-on.
+    pub fn read() -> Self {
+        let (reason, chip_reset) = (WATCHDOG.reason().read(), VREG_AND_CHIP_RESET.chip_reset().read());
+        if reason.timer() {
+            Self::WatchdogTimeout
+        } else if reason.force() {
+            Self::WatchdogTrigger
+        } else if chip_reset.had_psm_restart() {
+            Self::Debugger
+        } else if chip_reset.had_run() {
+            Self::RunPin
+        } else if chip_reset.had_por() {
+            Self::PowerOn
+        } else {
+            Self::Unknown
+        }
     }
 
-    /// Loads the last-persisted lifecycle
-    pub fn load() -> Option<u32> {
-        let lifecycle = WATCHDOG.scratch0().read();
-        let checksum = WATCHDOG.scratch1().read();
-        (lifecycle == (checksum ^ Self::CHECKSUM_XOR)).then_some(lifecycle)
+    /// Whether the reset was caused by an unstable supply voltage, so the scratch registers are unreliable
+    ///
+    /// # Note
+    /// This is always false on the RP2040, see [`Self::PowerOn`].
+    pub const fn is_brownout(self) -> bool {
+        false
+    }
+}
+impl Display for ResetCause {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::PowerOn => write!(f, "power-on"),
+            Self::RunPin => write!(f, "run pin"),
+            Self::Debugger => write!(f, "debugger"),
+            Self::WatchdogTimeout => write!(f, "watchdog timeout"),
+            Self::WatchdogTrigger => write!(f, "watchdog trigger"),
+            Self::Unknown => write!(f, "unknown"),
+        }
     }
 }
 
@@ -52,18 +90,36 @@ impl Lifecycle {
 pub struct Watchdog {
     /// Underlying watchdog peripheral
     watchdog: Option<Peri<'static, WATCHDOG>>,
+    /// The hardware watchdog timeout
+    timeout: Duration,
     /// Watchdog deadline in seconds
     deadline_secs: AtomicU32,
 }
 impl Watchdog {
-    /// The watchdog timeout (currently ~8s)
+    /// The default and maximum watchdog timeout (currently ~8s)
+    // Note: The hardware counter is limited to 0xFFFFFF, but decrements twice per tick (see errata RP2040-E1)
     pub const TIMEOUT: Duration = Duration::from_micros(0xFFFFFF / 2);
-    /// The watchdog feeding interval with a sufficient safety margin
-    pub const FEED_INTERVAL: Duration = Duration::from_micros((Watchdog::TIMEOUT.as_micros() / 100) * 75);
+    /// The watchdog feeding interval for the default timeout
+    pub const FEED_INTERVAL: Duration = Self::feed_interval(Self::TIMEOUT);
 
     /// Creates a new watchdog instance from the peripheral
     pub const fn new(peripheral: Peri<'static, WATCHDOG>) -> Self {
-        Self { watchdog: Some(peripheral), deadline_secs: AtomicU32::new(0) }
+        Self::new_with_timeout(peripheral, Self::TIMEOUT)
+    }
+
+    /// Creates a new watchdog instance from the peripheral with a custom hardware timeout
+    ///
+    /// # Panics
+    /// This function panics if the timeout is zero or exceeds the hardware maximum [`Self::TIMEOUT`].
+    pub const fn new_with_timeout(peripheral: Peri<'static, WATCHDOG>, timeout: Duration) -> Self {
+        assert!(timeout.as_ticks() > 0, "watchdog timeout must not be zero");
+        assert!(timeout.as_ticks() <= Self::TIMEOUT.as_ticks(), "watchdog timeout exceeds the hardware maximum");
+        Self { watchdog: Some(peripheral), timeout, deadline_secs: AtomicU32::new(0) }
+    }
+
+    /// The watchdog feeding interval for the given timeout with a sufficient safety margin
+    pub const fn feed_interval(timeout: Duration) -> Duration {
+        Duration::from_micros((timeout.as_micros() / 100) * 75)
     }
 
     /// Starts the watchdog and setups the controller with the given initial timeout
@@ -79,14 +135,14 @@ impl Watchdog {
 
         // Start watchdog
         watchdog.pause_on_debug(true);
-        watchdog.start(Self::TIMEOUT);
+        watchdog.start(self.timeout);
 
         // Configure the watchdog so it resets everything, including ROSC/XOSC
         // Note: This is an additional safety measurement as we do some funny stuff with our clocks during sleep
         PSM.wdsel().write_value(Wdsel(0x0001ffff));
 
         // Initialize controlplane, set initial timeout and start task
-        spawner.must_spawn(watchdog_task(&self.deadline_secs, watchdog));
+        spawner.must_spawn(watchdog_task(&self.deadline_secs, Self::feed_interval(self.timeout), watchdog));
         controller
     }
 }
@@ -106,6 +162,36 @@ impl WatchdogController {
         self.deadline_secs.store(deadline_secs, Ordering::SeqCst);
     }
 
+    /// The remaining time until the watchdog deadline expires
+    pub fn remaining(&self) -> Duration {
+        let deadline = Instant::from_secs(self.deadline_secs.load(Ordering::SeqCst) as u64);
+        deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Busy-waits for the given duration while feeding the watchdog, so that long synchronous sections outside of the
+    /// executor do not trigger a spurious reset
+    ///
+    /// # Note
+    /// The watchdog is fed with the maximum hardware timeout, and like the watchdog task, feeding stops once the
+    /// deadline has expired.
+    pub fn delay_fed(&self, duration: Duration) {
+        /// The maximum watchdog hardware timeout counter
+        // Note: The counter decrements twice per tick (see errata RP2040-E1), so this is equivalent to [`Watchdog::TIMEOUT`]
+        const TIMEOUT_COUNTER: u32 = 0xFFFFFF;
+
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            if self.remaining() > Duration::MIN {
+                // Feed watchdog manually as the watchdog task cannot run while we are blocking
+                // Note: This is sound, since `Self` only exists if the watchdog has been started already
+                WATCHDOG.load().write_value(Load(TIMEOUT_COUNTER));
+            }
+
+            // Block until the next feed or the end of the delay
+            block_for(Watchdog::FEED_INTERVAL.min(end.saturating_duration_since(Instant::now())));
+        }
+    }
+
     /// Feeds the watchdog and performs a light-sleep for the given duration, then performs a graceful reset
     pub fn reset_after(self, rtc: Peri<'static, RTC>, mut scb: SCB, timeout: Duration) -> ! {
         /// The watchdog feed interval in seconds
@@ -117,6 +203,18 @@ impl WatchdogController {
         // Note: The maximum setting is 0xffffff which corresponds to 0xffffff / 2 ticks before triggering a watchdog
         //  reset (see errata RP2040-E1).
         const TIMEOUT_COUNTER: u32 = 0xFFFFFF;
+        /// The XOSC frequency which drives the system clock during sleep
+        const XOSC_HZ: u32 = 12_000_000;
+
+        // Check the crystal oscillator before switching the clocks to it, as an unstable XOSC would stall the sleep
+        // until the watchdog bites; in that case, fall back to a busy delay on the current clocks
+        let xosc = XOSC.status().read();
+        if !xosc.enabled() || !xosc.stable() {
+            debug_println!("[warn] xosc is not stable; falling back to busy delay for sleep");
+            self.set_timeout(timeout + Watchdog::FEED_INTERVAL);
+            self.delay_fed(timeout);
+            Self::graceful_reset()
+        }
 
         // Configure the watchdog so it resets everything incl ROSC/XOSC, and disable all interrupts except RTC
         // Note: This is an additional safety measurement as we do some funny stuff with our clocks during sleep
@@ -158,32 +256,59 @@ impl WatchdogController {
         });
 
         // Loop until the timeout is expired
-        let steps = timeout.as_micros() / Watchdog::FEED_INTERVAL.as_micros();
-        debug_println!("[info] sleeping for n intervals: {}", steps);
-        for _ in 0..steps {
+        // Note: Alarms are scheduled at absolute offsets from the initial datetime, so the sub-second remainder of
+        //  each interval does not accumulate, and the final interval is shortened to hit the timeout exactly
+        let timeout_secs = timeout.as_secs();
+        let mut elapsed_secs = 0;
+        debug_println!("[info] sleeping for n seconds: {}", timeout_secs);
+        while elapsed_secs < timeout_secs {
             // Feed watchdog manually as we don't have an owned high level instance
             // Note: This is sound, since `Self` only exists if the watchdog has been started already
             WATCHDOG.load().write_value(Load(TIMEOUT_COUNTER));
             debug_println!("[info] fed watchdog from lightsleep");
 
             // Schedule RTC alert
-            critical_section::with(|cs| {
+            let armed = critical_section::with(|cs| {
                 // Borrow RTC
                 let mut rtc_slot = Self::rtc().borrow_ref_mut(cs);
                 let rtc = rtc_slot.as_mut().expect("no rtc setup");
 
-                // Schedule next alert
+                // Compute the next target, but never schedule into the past if we are running late
                 let now = rtc.now().expect("failed to get current time");
-                let filter = DateTimeFilter::default().second((now.second + FEED_INTERVAL_SECS) % 60);
-                rtc.schedule_alarm(filter);
+                let now_secs = (now.day as u64 - 1) * 86_400 + now.hour as u64 * 3600 + now.minute as u64 * 60;
+                let now_secs = now_secs + now.second as u64;
+                elapsed_secs = (elapsed_secs + FEED_INTERVAL_SECS as u64).min(timeout_secs).max(now_secs + 1);
+
+                // Schedule next alert with carry into minutes and hours
+                // Note: The target is always less than a day ahead, so matching the time of day is unambiguous
+                let (hour, minute, second) =
+                    ((elapsed_secs / 3600 % 24) as u8, (elapsed_secs / 60 % 60) as u8, (elapsed_secs % 60) as u8);
+                rtc.schedule_alarm(DateTimeFilter::default().hour(hour).minute(minute).second(second));
+
+                // Read back the alarm configuration to ensure that the alarm is armed for the expected time
+                let (setup_0, setup_1) = (RTC.irq_setup_0().read(), RTC.irq_setup_1().read());
+                let time_matches = (setup_1.hour(), setup_1.min(), setup_1.sec()) == (hour, minute, second);
+                let time_enabled = setup_1.hour_ena() && setup_1.min_ena() && setup_1.sec_ena();
+                setup_0.match_active() && time_enabled && time_matches && RTC.inte().read().rtc()
             });
 
-            // Wait for interrupt
-            asm::wfi();
+            // Wait for interrupt, or fall back to a busy delay if the alarm is not armed
+            // Note: The system clock runs from XOSC during sleep, so we can derive the delay from its frequency
+            if armed {
+                asm::wfi();
+            } else {
+                debug_println!("[warn] failed to arm rtc alarm; falling back to busy delay");
+                asm::delay(XOSC_HZ * FEED_INTERVAL_SECS as u32);
+            }
         }
 
-        // Perform reset via watchdog (this also resets the clocks)
-        // Note: This should be sound as the existence of `self` implies the watchdog is running
+        // Perform reset via watchdog
+        Self::graceful_reset()
+    }
+
+    /// Performs a graceful reset via the watchdog (this also resets the clocks)
+    fn graceful_reset() -> ! {
+        // Note: This should be sound as it is only called via `self`, whose existence implies the watchdog is running
         debug_println!("[info] performing graceful reset");
         WATCHDOG.ctrl().write(|w| w.set_trigger(true));
         loop {
@@ -202,7 +327,11 @@ impl WatchdogController {
 
 /// [`Watchdog`] task
 #[embassy_executor::task]
-async fn watchdog_task(deadline_secs: &'static AtomicU32, mut watchdog: embassy_rp::watchdog::Watchdog) {
+async fn watchdog_task(
+    deadline_secs: &'static AtomicU32,
+    feed_interval: Duration,
+    mut watchdog: embassy_rp::watchdog::Watchdog,
+) {
     /// The current instant in secnds
     #[inline]
     fn now_secs() -> u32 {
@@ -211,19 +340,26 @@ async fn watchdog_task(deadline_secs: &'static AtomicU32, mut watchdog: embassy_
         u32::try_from(now).expect("instant timestamp is too large")
     }
 
+    let mut starved = false;
     loop {
         // Load and check the deadline from the controller
         if now_secs() <= deadline_secs.load(Ordering::SeqCst) {
             // Feed watchdog if the deadline has not expired yet
             watchdog.feed();
             debug_println!("[info] fed watchdog");
+            starved = false;
         } else {
             // Log a warning if the watchdog deadline has expired
             debug_println!("[warn] watchdog deadline expired");
+            if !starved {
+                // Count the expiry once before the likely reset, so the next boot can report it
+                Starvation::bump();
+                starved = true;
+            }
         }
 
         // Always yield some time to allow other tasks to run
-        Timer::after(Watchdog::FEED_INTERVAL).await;
+        Timer::after(feed_interval).await;
     }
 }
 
@@ -1,5 +1,6 @@
 MEMORY {
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K - 4k
+    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K - 8k
+    HISTORY : ORIGIN = 0x101FE000, LENGTH = 4k
     DATA  : ORIGIN = 0x101FF000, LENGTH = 4k
     RAM   : ORIGIN = 0x20000000, LENGTH = 512K + 8K
 }
@@ -50,6 +51,19 @@ SECTIONS {
 }
 
 
+/* ### Persistent reading history section
+ *
+ * Is used to buffer readings that could not be published
+ */
+SECTIONS {
+    .history (NOLOAD) :
+    {
+        KEEP(*(.history));
+        . = ALIGN(4);
+    } > HISTORY
+}
+
+
 /* ### Boot ROM extra info
  *
  * Goes after everything in our program, so it can contain a signature.
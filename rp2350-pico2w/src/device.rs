@@ -0,0 +1,14 @@
+//! Device identification
+
+use common::device::DeviceId;
+use embassy_rp::otp;
+
+/// Reads the device ID
+///
+/// # Note
+/// This uses the unique chip ID which is programmed into the OTP memory during manufacturing.
+pub fn read_id() -> DeviceId {
+    // Read the chip ID
+    let id = otp::get_chipid().expect("failed to read chip id");
+    DeviceId::new(id.to_be_bytes())
+}
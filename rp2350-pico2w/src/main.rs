@@ -1,39 +1,63 @@
 #![no_std]
 #![no_main]
 
-mod config;
-mod debug;
-mod mqtt;
-mod panic;
-mod sensor;
+mod device;
 mod watchdog;
-mod wifi;
 
-use crate::config::AppConfig;
-use crate::debug::{StatusLed, StatusLedMode};
-use crate::mqtt::{MqttBuffer, MqttStack};
-use crate::sensor::Sensor;
-use crate::watchdog::{Lifecycle, Watchdog};
-use crate::wifi::{Cyw43, Cyw43Config, Cyw43Session};
+use crate::watchdog::{ResetCause, Watchdog, WatchdogController};
+use common::app::{Board, Hardware};
+use common::device::DeviceId;
+use common::{APP_TIMEOUT, stack};
+use core::fmt::Display;
 use cortex_m::Peripherals;
+use cortex_m::peripheral::SCB;
 use embassy_executor::Spawner;
-use embassy_rp::bind_interrupts;
+use embassy_rp::Peri;
 use embassy_rp::clocks::ClockConfig;
 use embassy_rp::config::Config;
-use embassy_rp::peripherals::PIO0;
+use embassy_rp::peripherals::RTC;
 use embassy_time::Duration;
 use static_cell::StaticCell;
 
-/// The application timeout
-const APP_TIMEOUT: Duration = Duration::from_secs(45);
+/// The Raspberry Pi Pico 2 W glue for the application flow
+struct Pico2W {
+    /// The started watchdog
+    watchdog: WatchdogController,
+    /// The RTC peripheral for the sleep sequence
+    rtc: Peri<'static, RTC>,
+    /// The system control block for the sleep sequence
+    scb: SCB,
+    /// The hardware cause of the last reset
+    reset_cause: ResetCause,
+    /// The unique device ID
+    device_id: DeviceId,
+}
+impl Board for Pico2W {
+    const VERSION: &'static str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH"));
 
-// Bind required interrupt handlers
-bind_interrupts!(struct Irqs {
-    // PIO0 interrupt handler
-    PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
-    // ADC channel interrupt handler
-    ADC_IRQ_FIFO => embassy_rp::adc::InterruptHandler;
-});
+    fn reset_cause(&self) -> impl Display {
+        self.reset_cause
+    }
+    fn is_brownout(&self) -> bool {
+        self.reset_cause.is_brownout()
+    }
+    fn is_watchdog_timeout(&self) -> bool {
+        self.reset_cause == ResetCause::WatchdogTimeout
+    }
+    fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.watchdog.set_timeout(timeout);
+    }
+    fn remaining(&self) -> Duration {
+        self.watchdog.remaining()
+    }
+    async fn reset_after(self, timeout: Duration) -> ! {
+        self.watchdog.reset_after(self.rtc, self.scb, timeout).await
+    }
+}
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
@@ -42,114 +66,50 @@ async fn main(spawner: Spawner) {
 
     /// Static watchdog peripheral
     static WATCHDOG: StaticCell<Watchdog> = StaticCell::new();
-    /// Static CYW43 peripheral handle
-    static CYW43: StaticCell<Cyw43> = StaticCell::new();
-    /// Static radio control session
-    static RADIO: StaticCell<Cyw43Session> = StaticCell::new();
-    /// Static status LED control session
-    static LED: StaticCell<StatusLed> = StaticCell::new();
+
+    // Paint the unused stack first, so the high-water mark covers the entire cycle
+    stack::paint();
 
     // Setup device
     let mut hw_config = Config::default();
     hw_config.clocks = ClockConfig::system_freq(SYSTEM_FREQ_HZ).expect("failed to build clock config");
     let hw = embassy_rp::init(hw_config);
 
+    // Setup UART logging as early as possible if enabled
+    #[cfg(feature = "uart-log")]
+    common::debug::start_uart_log(hw.UART0, hw.PIN_0, hw.DMA_CH2, &spawner);
+
     // Get peripherals and take reset info before doing anything else
     let peripherals = Peripherals::take().expect("failed to take peripherals");
-    let lifecycle_before_reset = Lifecycle::load();
-    debug_println!("[info] lifecycle before reset: {:?}", lifecycle_before_reset);
+    let reset_cause = ResetCause::read();
 
     // Setup watchdog
     let watchdog = WATCHDOG.init(Watchdog::new(hw.WATCHDOG));
     let watchdog = watchdog.start(APP_TIMEOUT, &spawner);
-    Lifecycle::store(Lifecycle::WATCHDOG);
-    debug_println!("[info] watchdog initialized");
-
-    // Load device config
-    let config = AppConfig::load();
-    debug_println!("[info] loaded config: {:?}", config);
-
-    // Setup radio and init network stack
-    let radio =
-        CYW43.init(Cyw43Config::new(hw.PIO0, Irqs, hw.DMA_CH0).set_pins(hw.PIN_23, hw.PIN_25, hw.PIN_24, hw.PIN_29));
-    let (radio, network) = radio.boot(&spawner).await;
-    Lifecycle::store(Lifecycle::RADIOINIT);
-    debug_println!("[info] initialized radio");
-
-    // Setup radio and LED control sessions
-    let radio = RADIO.init(radio);
-    let led = LED.init(StatusLed::new(radio));
-    let led = led.start(&spawner);
 
-    // We now have everything set up to divert to the after-panic handler if appropriate
-    let true = matches!(lifecycle_before_reset, Some(Lifecycle::LIGHTSLEEP)) else {
-        // Apparently the previous app has not stopped gracefully
-        panic::after_panic(&led).await;
+    // Read device ID
+    let device_id = device::read_id();
+
+    // Hand the board over to the application flow
+    let board = Pico2W { watchdog, rtc: hw.RTC, scb: peripherals.SCB, reset_cause, device_id };
+    let hardware = Hardware {
+        adc: hw.ADC,
+        adc_temp_sensor: hw.ADC_TEMP_SENSOR,
+        dma_ch0: hw.DMA_CH0,
+        dma_ch1: hw.DMA_CH1,
+        pio0: hw.PIO0,
+        i2c0: hw.I2C0,
+        flash: hw.FLASH,
+        pin_4: hw.PIN_4,
+        pin_5: hw.PIN_5,
+        pin_22: hw.PIN_22,
+        pin_23: hw.PIN_23,
+        pin_24: hw.PIN_24,
+        pin_25: hw.PIN_25,
+        pin_26: hw.PIN_26,
+        pin_27: hw.PIN_27,
+        pin_28: hw.PIN_28,
+        pin_29: hw.PIN_29,
     };
-
-    //
-    // Enter main application logic
-    //
-    Lifecycle::store(Lifecycle::APPINIT);
-    led.set(StatusLedMode::On);
-
-    // Try to join network
-    radio.join(&config).await;
-    debug_println!("[info] joined wifi: {}", config.WIFI_SSID);
-
-    // Wait for link
-    network.wait_link_up().await;
-    debug_println!("[info] got network link");
-
-    // Wait for DHCP
-    network.wait_config_up().await;
-    debug_println!("[info] got dhcp config");
-
-    // Init MQTT stack
-    let mut mqtt = MqttStack::new(network);
-    let mqtt = mqtt.init(&config);
-
-    // Connect to MQTT server
-    let mqtt = mqtt.connect().await;
-    debug_println!("[info] connected to mqtt server");
-
-    // Establish MQTT session
-    let mut mqtt = mqtt.login().await;
-    debug_println!("[info] established mqtt session");
-
-    // Read sensor and chip temperature
-    // Note: The ADC draws some current, so ensure it is dropped asap
-    let mut sensor = Sensor::new(hw.ADC, Irqs, hw.DMA_CH1, hw.PIN_27, hw.PIN_28, hw.ADC_TEMP_SENSOR);
-    let readings = sensor.read().await;
-    drop(sensor);
-    debug_println!("[info] read sensor values");
-
-    // Scope the MQTT buffers due to stack size
-    {
-        // Publish sensor voltage
-        let sensor = MqttBuffer::from_display(readings.sensor);
-        mqtt.publish("voltage", &sensor).await;
-        debug_println!("[info] published sensor voltage: {}", readings.sensor);
-    }
-    {
-        // Publish chip temperature
-        let temperature_str = MqttBuffer::from_display(readings.temperature);
-        mqtt.publish("temperature", &temperature_str).await;
-        debug_println!("[info] published system temperature: {}", readings.temperature);
-    }
-
-    // Disconnect
-    mqtt.disconnect().await;
-    debug_println!("[info] disconnected from mqtt server");
-
-    // Shutdown radio (also turns LED off)
-    radio.shutdown().await;
-    debug_println!("[info] stopped radio");
-
-    //
-    // Sleep and perform reset
-    //
-    Lifecycle::store(Lifecycle::LIGHTSLEEP);
-    debug_println!("[info] entering sleep");
-    watchdog.reset_after(hw.RTC, peripherals.SCB, config.SENSOR_SLEEP_SECS).await;
+    common::app::run(board, hardware, spawner).await
 }
@@ -1,13 +1,15 @@
 //! Provides reset-after functionality
 
-use crate::debug_println;
+use common::debug_println;
+use common::lifecycle::Starvation;
+use core::fmt::{Display, Formatter};
 use core::sync::atomic::{AtomicU32, Ordering};
 use core::u32;
 use cortex_m::asm;
 use cortex_m::peripheral::{NVIC, SCB};
 use embassy_executor::Spawner;
 use embassy_rp::pac::clocks::vals::{ClkRefCtrlSrc, ClkSysCtrlSrc};
-use embassy_rp::pac::{CLOCKS, Interrupt, POWMAN, WATCHDOG};
+use embassy_rp::pac::{CLOCKS, Interrupt, POWMAN, SIO, WATCHDOG, XOSC};
 use embassy_rp::peripherals::{RTC, WATCHDOG};
 use embassy_rp::{Peri, interrupt};
 use embassy_time::{Duration, Instant, Timer};
@@ -27,33 +29,74 @@ macro_rules! write_reg {
     }};
 }
 
-/// Lifecycle hints that persist across resets
-#[derive(Debug, Clone, Copy)]
-pub struct Lifecycle;
-impl Lifecycle {
-    /// The watchdog has been started, but not much more happened
-    pub const WATCHDOG: u32 = 367300213;
-    /// The radio peripheral has been initialized
-    pub const RADIOINIT: u32 = 968074460;
-    /// The main application logic has been entered
-    pub const APPINIT: u32 = 3422455895;
-    /// The main application logic has finished
-    pub const LIGHTSLEEP: u32 = 156439317;
-
-    /// The scratch checksum XOR constant
-    const CHECKSUM_XOR: u32 = 0x2144DF9C;
-
-    /// Persists the current lifecycle
-    pub fn store(lifecycle: u32) {
-        WATCHDOG.scratch0().write_value(lifecycle);
-        WATCHDOG.scratch1().write_value(lifecycle ^ Self::CHECKSUM_XOR);
+/// The hardware cause of the last reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    /// A power-on reset
+    PowerOn,
+    /// A brown-out reset
+    BrownOut,
+    /// A reset by the power supply glitch detector
+    SupplyGlitch,
+    /// A reset via the `RUN` pin
+    RunPin,
+    /// A reset requested by the debugger
+    Debugger,
+    /// A watchdog timeout, e.g. because the firmware hung
+    WatchdogTimeout,
+    /// A reset triggered via the watchdog, e.g. a graceful reset after sleep
+    WatchdogTrigger,
+    /// No hardware cause is recorded, e.g. after a software system reset
+    Unknown,
+}
+impl ResetCause {
+    /// Reads the cause of the last reset from the watchdog and power manager reset registers
+    ///
+    /// # Note
+    /// The power manager reset register is not updated on software system resets, so it is checked after the watchdog
+    /// reason. After a software system reset (like the post-panic reset), it may still report an earlier cause.
+    pub fn read() -> Self {
+        let (reason, chip_reset) = (WATCHDOG.reason().read(), POWMAN.chip_reset().read());
+        if reason.timer() {
+            Self::WatchdogTimeout
+        } else if reason.force() {
+            Self::WatchdogTrigger
+        } else if chip_reset.had_dp_reset_req() || chip_reset.had_rescue() || chip_reset.had_hzd_sys_reset_req() {
+            Self::Debugger
+        } else if chip_reset.had_glitch_detect() {
+            Self::SupplyGlitch
+        } else if chip_reset.had_run_low() {
+            Self::RunPin
+        } else if chip_reset.had_bor() {
+            Self::BrownOut
+        } else if chip_reset.had_por() {
+            Self::PowerOn
+        } else {
+            Self::Unknown
+        }
     }
 
-    /// Loads the last-persisted lifecycle
-    pub fn load() -> Option<u32> {
-        let lifecycle = WATCHDOG.scratch0().read();
-        let checksum = WATCHDOG.scratch1().read();
-        (lifecycle == (checksum ^ Self::CHECKSUM_XOR)).then_some(lifecycle)
+    /// Whether the reset was caused by an unstable supply voltage, so the scratch registers are unreliable
+    ///
+    /// # Note
+    /// A supply glitch only resets the processors, so the scratch registers may still hold the lifecycle of the
+    /// interrupted cycle.
+    pub const fn is_brownout(self) -> bool {
+        matches!(self, Self::BrownOut | Self::SupplyGlitch)
+    }
+}
+impl Display for ResetCause {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::PowerOn => write!(f, "power-on"),
+            Self::BrownOut => write!(f, "brown-out"),
+            Self::SupplyGlitch => write!(f, "supply glitch"),
+            Self::RunPin => write!(f, "run pin"),
+            Self::Debugger => write!(f, "debugger"),
+            Self::WatchdogTimeout => write!(f, "watchdog timeout"),
+            Self::WatchdogTrigger => write!(f, "watchdog trigger"),
+            Self::Unknown => write!(f, "unknown"),
+        }
     }
 }
 
@@ -61,18 +104,34 @@ impl Lifecycle {
 pub struct Watchdog {
     /// Underlying watchdog peripheral
     watchdog: Option<Peri<'static, WATCHDOG>>,
+    /// The hardware watchdog timeout
+    timeout: Duration,
     /// Watchdog deadline in seconds
     deadline_secs: AtomicU32,
 }
 impl Watchdog {
-    /// The watchdog timeout (currently ~16s)
+    /// The default and maximum watchdog timeout (currently ~16s)
+    // Note: The hardware counter is limited to 0xFFFFFF ticks
     pub const TIMEOUT: Duration = Duration::from_micros(0xFFFFFF);
-    /// The watchdog feeding interval with a sufficient safety margin
-    pub const FEED_INTERVAL: Duration = Duration::from_micros((Watchdog::TIMEOUT.as_micros() / 100) * 75);
 
     /// Creates a new watchdog instance from the peripheral
     pub const fn new(peripheral: Peri<'static, WATCHDOG>) -> Self {
-        Self { watchdog: Some(peripheral), deadline_secs: AtomicU32::new(0) }
+        Self::new_with_timeout(peripheral, Self::TIMEOUT)
+    }
+
+    /// Creates a new watchdog instance from the peripheral with a custom hardware timeout
+    ///
+    /// # Panics
+    /// This function panics if the timeout is zero or exceeds the hardware maximum [`Self::TIMEOUT`].
+    pub const fn new_with_timeout(peripheral: Peri<'static, WATCHDOG>, timeout: Duration) -> Self {
+        assert!(timeout.as_ticks() > 0, "watchdog timeout must not be zero");
+        assert!(timeout.as_ticks() <= Self::TIMEOUT.as_ticks(), "watchdog timeout exceeds the hardware maximum");
+        Self { watchdog: Some(peripheral), timeout, deadline_secs: AtomicU32::new(0) }
+    }
+
+    /// The watchdog feeding interval for the given timeout with a sufficient safety margin
+    pub const fn feed_interval(timeout: Duration) -> Duration {
+        Duration::from_micros((timeout.as_micros() / 100) * 75)
     }
 
     /// Starts the watchdog and setups the controller with the given initial timeout
@@ -88,10 +147,10 @@ impl Watchdog {
 
         // Start watchdog
         watchdog.pause_on_debug(true);
-        watchdog.start(Self::TIMEOUT);
+        watchdog.start(self.timeout);
 
         // Initialize controlplane, set initial timeout and start task
-        spawner.must_spawn(watchdog_task(&self.deadline_secs, watchdog));
+        spawner.must_spawn(watchdog_task(&self.deadline_secs, Self::feed_interval(self.timeout), watchdog));
         controller
     }
 }
@@ -111,8 +170,35 @@ impl WatchdogController {
         self.deadline_secs.store(deadline_secs, Ordering::SeqCst);
     }
 
+    /// The remaining time until the watchdog deadline expires
+    pub fn remaining(&self) -> Duration {
+        let deadline = Instant::from_secs(self.deadline_secs.load(Ordering::SeqCst) as u64);
+        deadline.saturating_duration_since(Instant::now())
+    }
+
     /// Feeds the watchdog and performs a light-sleep for the given duration, then performs a graceful reset
     pub async fn reset_after(self, _rtc: Peri<'static, RTC>, mut scb: SCB, timeout: Duration) -> ! {
+        /// The CYW43 powerselect pin
+        const RADIO_POWERSELECT_PIN: u32 = 23;
+
+        // Ensure the radio is powered down, as the status LED is driven by the radio and would stay lit during sleep
+        // Note: Every path should have shut down the radio already, so this is just a safety net
+        let powerselect = 1 << RADIO_POWERSELECT_PIN;
+        if SIO.gpio_out(0).value().read() & powerselect != 0 {
+            debug_println!("[warn] radio is still powered before sleep; forcing it off");
+            SIO.gpio_out(0).value_clr().write_value(powerselect);
+        }
+
+        // Check the crystal oscillator before switching the clocks to it, as an unstable XOSC would stall the sleep
+        // until the watchdog bites; in that case, fall back to a timer-based sleep fed by the watchdog task
+        let xosc = XOSC.status().read();
+        if !xosc.enabled() || !xosc.stable() {
+            debug_println!("[warn] xosc is not stable; falling back to timer-based sleep");
+            self.set_timeout(timeout + Watchdog::feed_interval(Watchdog::TIMEOUT));
+            Timer::after(timeout).await;
+            Self::graceful_reset()
+        }
+
         unsafe {
             // Disable all interrupts so WFI doesn't trigger unexpectedly
             (*NVIC::PTR).icer[0].write(u32::MAX);
@@ -162,6 +248,12 @@ impl WatchdogController {
         asm::wfi();
 
         // Perform a graceful reboot afterwards
+        Self::graceful_reset()
+    }
+
+    /// Performs a graceful reset via the watchdog (this also resets the clocks)
+    fn graceful_reset() -> ! {
+        // Note: This should be sound as it is only called via `self`, whose existence implies the watchdog is running
         debug_println!("[info] performing graceful reset");
         WATCHDOG.ctrl().write(|w| w.set_trigger(true));
         loop {
@@ -174,7 +266,11 @@ impl WatchdogController {
 
 /// [`Watchdog`] task
 #[embassy_executor::task]
-async fn watchdog_task(deadline_secs: &'static AtomicU32, mut watchdog: embassy_rp::watchdog::Watchdog) {
+async fn watchdog_task(
+    deadline_secs: &'static AtomicU32,
+    feed_interval: Duration,
+    mut watchdog: embassy_rp::watchdog::Watchdog,
+) {
     /// The current instant in secnds
     #[inline]
     fn now_secs() -> u32 {
@@ -183,19 +279,26 @@ async fn watchdog_task(deadline_secs: &'static AtomicU32, mut watchdog: embassy_
         u32::try_from(now).expect("instant timestamp is too large")
     }
 
+    let mut starved = false;
     loop {
         // Load and check the deadline from the controller
         if now_secs() <= deadline_secs.load(Ordering::SeqCst) {
             // Feed watchdog if the deadline has not expired yet
             watchdog.feed();
             debug_println!("[info] fed watchdog");
+            starved = false;
         } else {
             // Log a warning if the watchdog deadline has expired
             debug_println!("[warn] watchdog deadline expired");
+            if !starved {
+                // Count the expiry once before the likely reset, so the next boot can report it
+                Starvation::bump();
+                starved = true;
+            }
         }
 
         // Always yield some time to allow other tasks to run
-        Timer::after(Watchdog::FEED_INTERVAL).await;
+        Timer::after(feed_interval).await;
     }
 }
 